@@ -0,0 +1,35 @@
+#![allow(dead_code)]
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const THREADS: u64 = 4;
+
+/// Time `iters` calls of `run` split over `THREADS` threads.
+pub fn contended<F>(iters: u64, run: F) -> Duration
+where
+  F: Fn() + Send + Sync + 'static
+{
+  let run = Arc::new(run);
+  let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+  let handles: Vec<_> = (0..THREADS)
+    .map(|_| {
+      let run = Arc::clone(&run);
+      let barrier = Arc::clone(&barrier);
+      thread::spawn(move || {
+        barrier.wait();
+        for _ in 0..iters / THREADS {
+          run();
+        }
+      })
+    })
+    .collect();
+
+  let start = Instant::now();
+  barrier.wait();
+  for handle in handles {
+    handle.join().unwrap();
+  }
+  start.elapsed()
+}
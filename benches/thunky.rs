@@ -0,0 +1,250 @@
+//! The paths of a thunky, next to `once_cell` and `futures::Shared` doing the
+//! same where they can: a finished thunky read on one thread and on several,
+//! waiters queued in `Wait` from one thread and from several, async waiters,
+//! and runs failing back to `Run`.
+//!
+//! Callbacks are plain closures, so a finished thunky is read without
+//! allocating.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::contended;
+use criterion::{
+  black_box,
+  criterion_group,
+  criterion_main,
+  BenchmarkId,
+  Criterion
+};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use futures::future::{join, join_all, FutureExt};
+use once_cell::sync::OnceCell;
+use thunky::{Resolver, Thunky, ThunkyBuilder};
+
+const WAITERS: [usize; 3] = [1, 16, 256];
+
+fn finished() -> Arc<Thunky<u64, &'static str>> {
+  let thunk = ThunkyBuilder::new().build(Box::new(
+    |resolver: Resolver<u64, &'static str>| resolver.resolve(Ok(1))
+  ));
+  thunk.run(|_| {});
+  thunk
+}
+
+fn failing() -> Arc<Thunky<u64, &'static str>> {
+  ThunkyBuilder::new().build(Box::new(
+    |resolver: Resolver<u64, &'static str>| resolver.resolve(Err("down"))
+  ))
+}
+
+fn once_cell() -> Arc<OnceCell<u64>> {
+  let cell = Arc::new(OnceCell::new());
+  cell.set(1).unwrap();
+  cell
+}
+
+fn bench_finished(c: &mut Criterion) {
+  let mut group = c.benchmark_group("finished");
+
+  let thunk = finished();
+  group.bench_function("thunky run", |b| {
+    b.iter(|| {
+      thunk.run(|arg| {
+        black_box(arg);
+      })
+    })
+  });
+  group.bench_function("thunky get", |b| {
+    b.iter(|| black_box(block_on(thunk.get())))
+  });
+
+  let cell = once_cell();
+  group.bench_function("once_cell", |b| {
+    b.iter(|| black_box(cell.get_or_init(|| 2)))
+  });
+
+  let shared = futures::future::ready(1u64).shared();
+  block_on(shared.clone());
+  group.bench_function("shared", |b| {
+    b.iter(|| black_box(block_on(shared.clone())))
+  });
+
+  group.finish();
+}
+
+fn bench_contended(c: &mut Criterion) {
+  let mut group = c.benchmark_group("finished contended");
+
+  let thunk = finished();
+  group.bench_function("thunky run", |b| {
+    b.iter_custom(|iters| {
+      let thunk = Arc::clone(&thunk);
+      contended(iters, move || {
+        thunk.run(|arg| {
+          black_box(arg);
+        })
+      })
+    })
+  });
+
+  let cell = once_cell();
+  group.bench_function("once_cell", |b| {
+    b.iter_custom(|iters| {
+      let cell = Arc::clone(&cell);
+      contended(iters, move || {
+        black_box(cell.get_or_init(|| 2));
+      })
+    })
+  });
+
+  group.finish();
+}
+
+fn bench_waiters(c: &mut Criterion) {
+  let mut group = c.benchmark_group("waiters");
+
+  for waiters in WAITERS {
+    group.bench_with_input(
+      BenchmarkId::new("thunky run", waiters),
+      &waiters,
+      |b, &waiters| {
+        b.iter(|| {
+          let resolvers = Arc::new(Mutex::new(Vec::new()));
+          let resolvers_clone = Arc::clone(&resolvers);
+          let thunk = ThunkyBuilder::new().build(Box::new(
+            move |resolver: Resolver<u64, &'static str>| {
+              resolvers_clone.lock().unwrap().push(resolver);
+            }
+          ));
+
+          for _ in 0..waiters {
+            thunk.run(|arg| {
+              black_box(arg);
+            });
+          }
+          let resolver = resolvers.lock().unwrap().pop().unwrap();
+          resolver.resolve(Ok(1));
+        })
+      }
+    );
+  }
+
+  group.finish();
+}
+
+fn bench_waiters_contended(c: &mut Criterion) {
+  let mut group = c.benchmark_group("waiters contended");
+
+  group.bench_function("thunky run", |b| {
+    b.iter_custom(|iters| {
+      let resolvers = Arc::new(Mutex::new(Vec::new()));
+      let resolvers_clone = Arc::clone(&resolvers);
+      let thunk = ThunkyBuilder::new().build(Box::new(
+        move |resolver: Resolver<u64, &'static str>| {
+          resolvers_clone.lock().unwrap().push(resolver);
+        }
+      ));
+      thunk.run(|_| {});
+
+      let queued = Arc::clone(&thunk);
+      let elapsed = contended(iters, move || {
+        queued.run(|arg| {
+          black_box(arg);
+        })
+      });
+      let resolver = resolvers.lock().unwrap().pop().unwrap();
+      resolver.resolve(Ok(1));
+      elapsed
+    })
+  });
+
+  group.finish();
+}
+
+fn bench_async_waiters(c: &mut Criterion) {
+  let mut group = c.benchmark_group("async waiters");
+
+  for waiters in WAITERS {
+    group.bench_with_input(
+      BenchmarkId::new("thunky get", waiters),
+      &waiters,
+      |b, &waiters| {
+        b.iter(|| {
+          let (tx, rx) = oneshot::channel::<u64>();
+          let rx = Mutex::new(Some(rx));
+          let thunk = ThunkyBuilder::new().build_async(move || {
+            let rx = rx.lock().unwrap().take().unwrap();
+            async move { rx.await.map_err(|_| thunky::Error::Dropped) }
+          });
+
+          let gets = join_all((0..waiters).map(|_| thunk.get()));
+          let send = async move { tx.send(1).unwrap() };
+          black_box(block_on(join(gets, send)));
+        })
+      }
+    );
+
+    group.bench_with_input(
+      BenchmarkId::new("shared", waiters),
+      &waiters,
+      |b, &waiters| {
+        b.iter(|| {
+          let (tx, rx) = oneshot::channel::<u64>();
+          let shared = rx.map(|value| value.unwrap()).shared();
+
+          let gets = join_all((0..waiters).map(|_| shared.clone()));
+          let send = async move { tx.send(1).unwrap() };
+          black_box(block_on(join(gets, send)));
+        })
+      }
+    );
+  }
+
+  group.finish();
+}
+
+fn bench_errors(c: &mut Criterion) {
+  let mut group = c.benchmark_group("err to run");
+
+  let thunk = failing();
+  group.bench_function("thunky run", |b| {
+    b.iter(|| {
+      thunk.run(|arg| {
+        black_box(arg);
+      })
+    })
+  });
+
+  let cell = OnceCell::<u64>::new();
+  group.bench_function("once_cell", |b| {
+    b.iter(|| black_box(cell.get_or_try_init(|| Err::<u64, _>("down"))))
+  });
+
+  let thunk = failing();
+  group.bench_function("thunky run contended", |b| {
+    b.iter_custom(|iters| {
+      let thunk = Arc::clone(&thunk);
+      contended(iters, move || {
+        thunk.run(|arg| {
+          black_box(arg);
+        })
+      })
+    })
+  });
+
+  group.finish();
+}
+
+criterion_group!(
+  benches,
+  bench_finished,
+  bench_contended,
+  bench_waiters,
+  bench_waiters_contended,
+  bench_async_waiters,
+  bench_errors
+);
+criterion_main!(benches);
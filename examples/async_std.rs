@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+use thunky::rt::{AsyncStd, Runtime};
+use thunky::Thunky;
+
+fn main() {
+  async_std::task::block_on(async {
+    let thunk = Thunky::from_async_on(AsyncStd, || async {
+      AsyncStd.sleep(Duration::from_millis(500)).await;
+      Ok::<&str, &str>("resolved once on async-std")
+    });
+
+    let (a, b) = futures::join!(thunk.get(), thunk.get());
+    println!("{} / {}", a.unwrap(), b.unwrap());
+  });
+}
@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+use thunky::rt::{Runtime, Smol};
+use thunky::Thunky;
+
+fn main() {
+  smol::block_on(async {
+    let thunk = Thunky::from_async_on(Smol, || async {
+      Smol.sleep(Duration::from_millis(500)).await;
+      Ok::<&str, &str>("resolved once on smol")
+    });
+
+    let (a, b) = futures::join!(thunk.get(), thunk.get());
+    println!("{} / {}", a.unwrap(), b.unwrap());
+  });
+}
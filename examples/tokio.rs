@@ -0,0 +1,15 @@
+use std::time::Duration;
+
+use thunky::rt::{Runtime, Tokio};
+use thunky::Thunky;
+
+#[tokio::main]
+async fn main() {
+  let thunk = Thunky::from_async_on(Tokio::current(), || async {
+    Tokio::current().sleep(Duration::from_millis(500)).await;
+    Ok::<&str, &str>("resolved once on tokio")
+  });
+
+  let (a, b) = tokio::join!(thunk.get(), thunk.get());
+  println!("{} / {}", a.unwrap(), b.unwrap());
+}
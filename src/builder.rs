@@ -0,0 +1,587 @@
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use crate::cancel::{Cancellable, Cancellation};
+use crate::circuit::CircuitBreaker;
+use crate::clock::{Clock, SystemClock};
+use crate::rt::Runtime;
+use crate::task::Task;
+use crate::map::Eviction;
+use crate::policy::{CachePolicy, DefaultPolicy};
+use crate::retry::Retry;
+use crate::timer::{ThreadTimer, Timer};
+use crate::{Error, MapRunCb, Resolver, RunCb, Thunky, ThunkyMap, Weigher};
+
+/// Options shared by all states of a thunky.
+#[derive(Clone)]
+pub(crate) struct Options {
+  pub(crate) ttl: Option<Duration>,
+  pub(crate) stale_while_revalidate: bool,
+  pub(crate) max_stale: Option<Duration>,
+  pub(crate) clock: Arc<dyn Clock>,
+  pub(crate) retry: Option<Retry>,
+  pub(crate) timer: Arc<dyn Timer>,
+  pub(crate) circuit_breaker: Option<CircuitBreaker>,
+  pub(crate) init_timeout: Option<Duration>,
+  pub(crate) cancellation: Cancellation
+}
+
+/// Options of a thunky map, on top of the options of its thunkies.
+#[derive(Clone)]
+pub(crate) struct MapOptions {
+  pub(crate) capacity: Option<usize>,
+  pub(crate) max_weight: Option<u64>,
+  pub(crate) eviction: Eviction
+}
+
+/// Configure a thunky before creating it.
+///
+/// `Thunky::new()`, `Thunky::from_async()`, `Thunky::from_async_on()` and
+/// `ThunkyMap::new()` are shorthands for building with the default options.
+///
+/// # Examples
+///
+/// ```
+/// // test: builder with ttl
+///
+/// extern crate thunky;
+///
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use thunky::*;
+///
+/// let clock = Arc::new(ManualClock::new());
+///
+/// let thunk = ThunkyBuilder::new()
+///   .ttl(Duration::from_secs(60))
+///   .clock(clock.clone())
+///   .build(|resolver: Resolver<u32, &str>| resolver.resolve(Ok(1)));
+///
+/// thunk.run(|arg: &Result<u32, &str>| {
+///   assert_eq!(1, arg.unwrap());
+/// });
+/// ```
+pub struct ThunkyBuilder<P = DefaultPolicy> {
+  options: Options,
+  map: MapOptions,
+  policy: P
+}
+
+impl ThunkyBuilder {
+  pub fn new() -> ThunkyBuilder {
+    ThunkyBuilder {
+      options: Options {
+        ttl: None,
+        stale_while_revalidate: false,
+        max_stale: None,
+        clock: Arc::new(SystemClock),
+        retry: None,
+        timer: Arc::new(ThreadTimer),
+        circuit_breaker: None,
+        init_timeout: None,
+        cancellation: Cancellation::Detached
+      },
+      map: MapOptions {
+        capacity: None,
+        max_weight: None,
+        eviction: Eviction::default()
+      },
+      policy: DefaultPolicy
+    }
+  }
+}
+
+impl<P> ThunkyBuilder<P> {
+  /// Expire a cached `Ok(T)` after `ttl`.
+  ///
+  /// Once expired the thunky is `Status::Expired`, so the next `thunky.run()`
+  /// calls the run function again. A ttl too long to be added to the time of
+  /// the clock, like `Duration::MAX`, never expires.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: ttl expires cached value
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::Arc;
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let clock = Arc::new(ManualClock::new());
+  /// let v = AtomicUsize::new(0);
+  ///
+  /// let run = move |resolver: Resolver<usize, &str>| {
+  ///   resolver.resolve(Ok(v.fetch_add(1, Ordering::SeqCst) + 1));
+  /// };
+  ///
+  /// let thunk = ThunkyBuilder::new()
+  ///   .ttl(Duration::from_secs(60))
+  ///   .clock(clock.clone())
+  ///   .build(run);
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// clock.advance(Duration::from_secs(30));
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// clock.advance(Duration::from_secs(30));
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(2, arg.unwrap());
+  /// });
+  /// ```
+  pub fn ttl(mut self, ttl: Duration) -> ThunkyBuilder<P> {
+    self.options.ttl = Some(ttl);
+    self
+  }
+
+  /// Keep serving an expired `Ok(T)` while it is being refreshed.
+  ///
+  /// After the ttl expires, `thunky.run()` calls its callback with the stale
+  /// value right away, and calls the run function once to refresh it. Until
+  /// the refresh is cached, callbacks keep getting the stale value. If the
+  /// refresh fails the stale value is kept, and the next `thunky.run()` tries
+  /// again.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: stale value served while refreshing
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::Arc;
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let clock = Arc::new(ManualClock::new());
+  /// let v = AtomicUsize::new(0);
+  ///
+  /// let thunk = ThunkyBuilder::new()
+  ///   .ttl(Duration::from_secs(60))
+  ///   .stale_while_revalidate()
+  ///   .clock(clock.clone())
+  ///   .build(move |resolver: Resolver<usize, &str>| {
+  ///     resolver.resolve(Ok(v.fetch_add(1, Ordering::SeqCst) + 1));
+  ///   });
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// clock.advance(Duration::from_secs(60));
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(2, arg.unwrap());
+  /// });
+  /// ```
+  pub fn stale_while_revalidate(mut self) -> ThunkyBuilder<P> {
+    self.options.stale_while_revalidate = true;
+    self
+  }
+
+  /// Stop serving a stale value `max_stale` after it expired.
+  ///
+  /// Past that, `thunky.run()` waits for the refresh like after a plain ttl
+  /// expiry. Only used with `stale_while_revalidate()`. `Duration::MAX`
+  /// serves a stale value forever, like not setting it.
+  pub fn max_stale(mut self, max_stale: Duration) -> ThunkyBuilder<P> {
+    self.options.max_stale = Some(max_stale);
+    self
+  }
+
+  /// Use `clock` instead of the system clock to expire cached values.
+  pub fn clock(mut self, clock: Arc<dyn Clock>) -> ThunkyBuilder<P> {
+    self.options.clock = clock;
+    self
+  }
+
+  /// Keep at most `capacity` keys in a thunky map, evicting the least recently
+  /// used one when a new key is added. Only used by `build_map()`.
+  ///
+  /// Keys whose run is in progress are never evicted, so their callbacks are
+  /// still called: while all keys are running, the map grows past `capacity`,
+  /// and shrinks back as new keys are added. An evicted key is just like a
+  /// removed one, its next `run()` calls the run function again.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: map evicts least recently used
+  ///
+  /// extern crate thunky;
+  ///
+  /// use thunky::*;
+  ///
+  /// let map = ThunkyBuilder::new()
+  ///   .capacity(2)
+  ///   .build_map(|key: &u32, resolver: Resolver<u32, &str>| {
+  ///     resolver.resolve(Ok(*key))
+  ///   });
+  ///
+  /// for key in &[1, 2, 1, 3] {
+  ///   map.run(*key, |_arg: &Result<u32, &str>| {});
+  /// }
+  ///
+  /// assert!(map.contains_key(&1));
+  /// assert!(!map.contains_key(&2));
+  /// assert!(map.contains_key(&3));
+  /// ```
+  pub fn capacity(mut self, capacity: usize) -> ThunkyBuilder<P> {
+    self.map.capacity = Some(capacity);
+    self
+  }
+
+  /// Keep the total weight of the values cached by a thunky map under
+  /// `max_weight`, evicting keys like `capacity()` does. Only used by
+  /// `build_weighted_map()`, whose weigher measures each cached `Ok(T)`.
+  ///
+  /// Values are measured as soon as their run is settled, and keys are evicted
+  /// then. Expired values stop counting when the next key is added. The key
+  /// just resolved or used is not evicted, even if its value alone is over
+  /// budget.
+  /// `ThunkyMap::stats()` tells how much is evicted.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: map evicts over weight budget
+  ///
+  /// extern crate thunky;
+  ///
+  /// use thunky::*;
+  ///
+  /// let map = ThunkyBuilder::new()
+  ///   .max_weight(1024)
+  ///   .build_weighted_map(
+  ///     |value: &Vec<u8>| value.len() as u64,
+  ///     |key: &usize, resolver: Resolver<Vec<u8>, &str>| {
+  ///       resolver.resolve(Ok(vec![0; *key]))
+  ///     }
+  ///   );
+  ///
+  /// for key in &[512, 256, 1000] {
+  ///   map.run(*key, |_arg: &Result<Vec<u8>, &str>| {});
+  /// }
+  ///
+  /// let stats = map.stats();
+  /// assert_eq!(1, stats.len);
+  /// assert_eq!(1000, stats.weight);
+  /// assert_eq!(2, stats.evictions);
+  /// assert_eq!(768, stats.evicted_weight);
+  /// ```
+  pub fn max_weight(mut self, max_weight: u64) -> ThunkyBuilder<P> {
+    self.map.max_weight = Some(max_weight);
+    self
+  }
+
+  /// Choose which key a thunky map evicts once it's at its `capacity()`.
+  /// Defaults to `Eviction::Lru`.
+  pub fn eviction(mut self, eviction: Eviction) -> ThunkyBuilder<P> {
+    self.map.eviction = eviction;
+    self
+  }
+
+  /// Decide with `policy` which results are cached, instead of caching
+  /// `Ok(T)` and skipping `Err(E)`.
+  ///
+  /// A cached `Err(E)` is delivered like a cached `Ok(T)`, until it expires.
+  /// A skipped `Ok(T)` is delivered to the waiting callbacks only, like an
+  /// `Err(E)` by default. With `stale_while_revalidate()`, a skipped refresh
+  /// keeps the stale value.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: negative caching with policy
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let calls = AtomicUsize::new(0);
+  ///
+  /// let thunk = ThunkyBuilder::new()
+  ///   .policy(|result: &Result<u32, &str>| match result {
+  ///     Err("not found") => Decision::CacheFor(Duration::from_secs(60)),
+  ///     Err(_) => Decision::Skip,
+  ///     Ok(_) => Decision::Cache
+  ///   })
+  ///   .build(move |resolver: Resolver<u32, &str>| {
+  ///     calls.fetch_add(1, Ordering::SeqCst);
+  ///     resolver.resolve(Err("not found"));
+  ///   });
+  ///
+  /// for _ in 0..2 {
+  ///   thunk.run(|arg: &Result<u32, &str>| {
+  ///     assert_eq!(Err("not found"), *arg);
+  ///   });
+  /// }
+  /// ```
+  pub fn policy<Q>(self, policy: Q) -> ThunkyBuilder<Q> {
+    ThunkyBuilder {
+      options: self.options,
+      map: self.map,
+      policy
+    }
+  }
+
+  /// Call the run function again when it fails, as configured by `retry`,
+  /// before delivering the error.
+  ///
+  /// An `Err(E)` which the policy doesn't cache is not delivered until the
+  /// run function was called `retry.max_attempts()` times. Meanwhile the
+  /// callbacks keep waiting, and the thunky waits for the delay of each retry
+  /// on its `timer()`. An `Ok(T)` or a cached `Err(E)` ends the retries.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: retry until ok
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let clock = Arc::new(ManualClock::new());
+  /// let calls = AtomicUsize::new(0);
+  /// let results = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let retry = Retry::new(3)
+  ///   .backoff(Duration::from_secs(1), Duration::from_secs(10));
+  ///
+  /// let thunk = ThunkyBuilder::new()
+  ///   .retry(retry)
+  ///   .timer(clock.clone())
+  ///   .build(move |resolver: Resolver<usize, &'static str>| {
+  ///     match calls.fetch_add(1, Ordering::SeqCst) {
+  ///       0 | 1 => resolver.resolve(Err("down")),
+  ///       n => resolver.resolve(Ok(n))
+  ///     }
+  ///   });
+  ///
+  /// let results_clone = Arc::clone(&results);
+  /// thunk.run(move |arg: &Result<usize, &'static str>| {
+  ///   results_clone.lock().unwrap().push(*arg);
+  /// });
+  ///
+  /// clock.advance(Duration::from_secs(1));
+  /// assert!(results.lock().unwrap().is_empty());
+  ///
+  /// clock.advance(Duration::from_secs(2));
+  /// assert_eq!(vec![Ok(2)], *results.lock().unwrap());
+  /// ```
+  pub fn retry(mut self, retry: Retry) -> ThunkyBuilder<P> {
+    self.options.retry = Some(retry);
+    self
+  }
+
+  /// Wait for retries and timeouts with `timer` instead of the thread shared
+  /// by default, see `ThreadTimer`.
+  ///
+  /// `ManualClock` is a timer too, to retry without sleeping in tests, and
+  /// `RuntimeTimer` waits on the executor of a `Runtime`.
+  pub fn timer(mut self, timer: Arc<dyn Timer>) -> ThunkyBuilder<P> {
+    self.options.timer = timer;
+    self
+  }
+
+  /// Stop calling the run function for a while after it failed repeatedly,
+  /// see `CircuitBreaker`.
+  ///
+  /// A run fails when an `Err(E)` is delivered to its callbacks: after its
+  /// retries, if any, and unless the policy caches it. Any other result closes
+  /// the circuit again. The cool-down is measured with the `clock()`, and
+  /// `thunky.circuit()` tells the state of the circuit.
+  ///
+  /// Failed refreshes of a stale value count too. While the circuit is open,
+  /// a stale value is still served, without a refresh.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: circuit opens after failures
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::Arc;
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let clock = Arc::new(ManualClock::new());
+  /// let calls = Arc::new(AtomicUsize::new(0));
+  ///
+  /// let calls_clone = Arc::clone(&calls);
+  /// let thunk = ThunkyBuilder::new()
+  ///   .circuit_breaker(CircuitBreaker::new(2, Duration::from_secs(30)))
+  ///   .clock(clock.clone())
+  ///   .build(move |resolver: Resolver<u32, &str>| {
+  ///     calls_clone.fetch_add(1, Ordering::SeqCst);
+  ///     resolver.resolve(Err("down"));
+  ///   });
+  ///
+  /// for _ in 0..2 {
+  ///   thunk.run(|arg: &Result<u32, &str>| {
+  ///     assert_eq!(Err("down"), *arg);
+  ///   });
+  /// }
+  /// assert_eq!(Circuit::Open, thunk.circuit());
+  ///
+  /// thunk.run(|arg: &Result<u32, &str>| {
+  ///   assert_eq!(Err("thunky circuit breaker is open"), *arg);
+  /// });
+  /// assert_eq!(2, calls.load(Ordering::SeqCst));
+  ///
+  /// clock.advance(Duration::from_secs(30));
+  /// thunk.run(|_arg: &Result<u32, &str>| {});
+  /// assert_eq!(3, calls.load(Ordering::SeqCst));
+  /// ```
+  pub fn circuit_breaker(
+    mut self,
+    circuit_breaker: CircuitBreaker
+  ) -> ThunkyBuilder<P> {
+    self.options.circuit_breaker = Some(circuit_breaker);
+    self
+  }
+
+  /// Fail a run with `Error::Timeout` if its resolver doesn't resolve it
+  /// within `timeout`.
+  ///
+  /// All the callbacks waiting for the run get the error, and the run is
+  /// superseded like with `thunky.reset()`, so its resolver is ignored from
+  /// then on. The timeout applies to each call of the run function, and is
+  /// waited for on the `timer()`. Like any error, it may be retried.
+  pub fn init_timeout(mut self, timeout: Duration) -> ThunkyBuilder<P> {
+    self.options.init_timeout = Some(timeout);
+    self
+  }
+
+  /// Decide what happens to a run when every future waiting for it, from
+  /// `thunky.get()`, is dropped or timed out. Defaults to
+  /// `Cancellation::Detached`.
+  ///
+  /// With `Cancellation::LastWaiter` the run is superseded and the thunky goes
+  /// back to `Status::Idle`. In this mode the future of `build_async()` is
+  /// dropped whenever its run is cancelled or superseded, and any run function
+  /// can stop early with `Resolver::cancelled()`. Callbacks passed to
+  /// `thunky.run()` can't go away, so they keep the run going.
+  pub fn cancellation(
+    mut self,
+    cancellation: Cancellation
+  ) -> ThunkyBuilder<P> {
+    self.options.cancellation = cancellation;
+    self
+  }
+
+  /// Create a thunky with a run function, see `Thunky::new()`.
+  pub fn build<T, E, F>(self, run: F) -> Arc<Thunky<T, E>>
+  where
+    P: CachePolicy<T, E> + 'static,
+    F: Fn(Resolver<T, E>) + Send + Sync + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    let options = self.options;
+    let policy = Arc::new(self.policy);
+    let run: RunCb<T, E> = Box::new(run);
+    Arc::new_cyclic(|this: &Weak<Thunky<T, E>>| {
+      Thunky::with_run(run, options, policy, E::from, this)
+    })
+  }
+
+  /// Create a map of thunkies, one per key, see `ThunkyMap::new()`.
+  ///
+  /// Every thunky of the map is built with the options of this builder.
+  pub fn build_map<K, T, E, F>(self, run: F) -> ThunkyMap<K, T, E>
+  where
+    P: CachePolicy<T, E> + 'static,
+    F: Fn(&K, Resolver<T, E>) + Send + Sync + 'static,
+    E: From<Error>
+  {
+    let policy = Arc::new(self.policy);
+    let run: MapRunCb<K, T, E> = Box::new(run);
+    ThunkyMap::with_run(run, self.options, self.map, policy, None)
+  }
+
+  /// Create a map of thunkies whose cached values are measured by `weigher`,
+  /// see `max_weight()`.
+  pub fn build_weighted_map<K, T, E, W, F>(
+    self,
+    weigher: W,
+    run: F
+  ) -> ThunkyMap<K, T, E>
+  where
+    P: CachePolicy<T, E> + 'static,
+    W: Weigher<T> + 'static,
+    F: Fn(&K, Resolver<T, E>) + Send + Sync + 'static,
+    E: From<Error>
+  {
+    let policy = Arc::new(self.policy);
+    let run: MapRunCb<K, T, E> = Box::new(run);
+    let weigher = Some(Arc::new(weigher) as Arc<dyn Weigher<T>>);
+    ThunkyMap::with_run(run, self.options, self.map, policy, weigher)
+  }
+
+  /// Create a thunky from an async function, see `Thunky::from_async()`.
+  pub fn build_async<T, E, F, Fut>(self, init: F) -> Arc<Thunky<T, E>>
+  where
+    P: CachePolicy<T, E> + 'static,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    let cancellation = self.options.cancellation;
+    self.build(move |resolver: Resolver<T, E>| {
+      let future = Cancellable::new(init(), cancellation, &resolver);
+      Task::spawn(future, resolver);
+    })
+  }
+
+  /// Create a thunky from an async function spawned on `runtime`, see
+  /// `Thunky::from_async_on()`.
+  pub fn build_async_on<T, E, R, F, Fut>(
+    self,
+    runtime: R,
+    init: F
+  ) -> Arc<Thunky<T, E>>
+  where
+    P: CachePolicy<T, E> + 'static,
+    R: Runtime,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    let cancellation = self.options.cancellation;
+    self.build(move |resolver: Resolver<T, E>| {
+      let future = Cancellable::new(init(), cancellation, &resolver);
+      runtime.spawn(Box::pin(async move {
+        resolver.resolve(future.await);
+      }));
+    })
+  }
+}
+
+impl Default for ThunkyBuilder {
+  fn default() -> ThunkyBuilder {
+    ThunkyBuilder::new()
+  }
+}
@@ -0,0 +1,122 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+
+use crate::sync::{AtomicBool, Mutex, Ordering};
+use crate::{Error, Resolver};
+
+/// What happens to a run when every future waiting for it is dropped, see
+/// `ThunkyBuilder::cancellation()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cancellation {
+  /// The run goes on, and its result is cached for the next waiters.
+  #[default]
+  Detached,
+  /// The run is cancelled once its last waiter goes away, and the thunky goes
+  /// back to `Run`.
+  LastWaiter
+}
+
+/// Cancelled when the run it was created for is superseded.
+pub(crate) struct Token {
+  cancelled: AtomicBool,
+  wakers: Mutex<Vec<Waker>>
+}
+
+impl Token {
+  pub(crate) fn new() -> Arc<Token> {
+    Arc::new(Token {
+      cancelled: AtomicBool::new(false),
+      wakers: Mutex::new(Vec::new())
+    })
+  }
+
+  pub(crate) fn is_cancelled(&self) -> bool {
+    self.cancelled.load(Ordering::SeqCst)
+  }
+
+  /// Cancel the token and wake the futures waiting for it. Must not be called
+  /// with the mutex of the thunky held, as a waker may poll right away.
+  pub(crate) fn cancel(&self) {
+    let wakers = {
+      let mut wakers = self.wakers.lock().unwrap();
+      self.cancelled.store(true, Ordering::SeqCst);
+      std::mem::take(&mut *wakers)
+    };
+    for waker in wakers {
+      waker.wake();
+    }
+  }
+}
+
+/// Future returned by `Resolver::cancelled()`, which resolves once the run is
+/// cancelled or superseded.
+pub struct Cancelled {
+  token: Arc<Token>
+}
+
+impl Cancelled {
+  pub(crate) fn new(token: Arc<Token>) -> Cancelled {
+    Cancelled { token }
+  }
+}
+
+impl Future for Cancelled {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+    let mut wakers = self.token.wakers.lock().unwrap();
+    if self.token.is_cancelled() {
+      return Poll::Ready(());
+    }
+    if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+      wakers.push(cx.waker().clone());
+    }
+    Poll::Pending
+  }
+}
+
+/// The future of an async initializer, dropped without being polled to the
+/// end if its run is cancelled, unless the thunky is `Cancellation::Detached`.
+pub(crate) struct Cancellable<F> {
+  future: Pin<Box<F>>,
+  cancelled: Option<Cancelled>
+}
+
+impl<F> Cancellable<F> {
+  pub(crate) fn new<T, E>(
+    future: F,
+    cancellation: Cancellation,
+    resolver: &Resolver<T, E>
+  ) -> Cancellable<F> {
+    let cancelled = match cancellation {
+      Cancellation::Detached => None,
+      Cancellation::LastWaiter => Some(resolver.cancelled())
+    };
+    Cancellable {
+      future: Box::pin(future),
+      cancelled
+    }
+  }
+}
+
+impl<T, E, F> Future for Cancellable<F>
+where
+  F: Future<Output = Result<T, E>>,
+  E: From<Error>
+{
+  type Output = Result<T, E>;
+
+  fn poll(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<Self::Output> {
+    if let Some(cancelled) = &mut self.cancelled {
+      if Pin::new(cancelled).poll(cx).is_ready() {
+        return Poll::Ready(Err(E::from(Error::Cancelled)));
+      }
+    }
+    self.future.as_mut().poll(cx)
+  }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Error, Thunky};
+
+/// The state of the circuit breaker of a thunky, see
+/// `ThunkyBuilder::circuit_breaker()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Circuit {
+  /// Runs call the run function.
+  Closed,
+  /// Runs fail with `Error::CircuitOpen` until the cool-down is over.
+  Open,
+  /// The cool-down is over and a single run probes the run function, closing
+  /// the circuit if it succeeds or opening it again if it fails.
+  HalfOpen
+}
+
+pub(crate) type Listener = Arc<dyn Fn(Circuit) + Send + Sync>;
+
+/// Stop calling a failing run function for a while.
+///
+/// After `threshold` consecutive failed runs, the circuit opens: for
+/// `cool_down`, `thunky.run()` doesn't call the run function and fails right
+/// away with `Error::CircuitOpen`. Then the circuit half-opens to let a probe
+/// through. With a `cool_down` of `Duration::MAX` it never half-opens.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+  threshold: u32,
+  cool_down: Duration,
+  listener: Option<Listener>
+}
+
+impl CircuitBreaker {
+  pub fn new(threshold: u32, cool_down: Duration) -> CircuitBreaker {
+    CircuitBreaker {
+      threshold,
+      cool_down,
+      listener: None
+    }
+  }
+
+  /// Call `listener` with the new state of the circuit on every transition.
+  ///
+  /// It's called once the thunky is unlocked, so it may use the thunky.
+  pub fn on_change<F>(mut self, listener: F) -> CircuitBreaker
+  where
+    F: Fn(Circuit) + Send + Sync + 'static
+  {
+    self.listener = Some(Arc::new(listener));
+    self
+  }
+}
+
+/// The circuit breaker state of a thunky, guarded by its mutex.
+pub(crate) struct Breaker<T, E> {
+  pub(crate) circuit: Circuit,
+  /// Consecutive failed runs.
+  failures: u32,
+  opened: Option<Instant>,
+  /// The `Error::CircuitOpen` runs fail with while open.
+  error: Option<Arc<Result<T, E>>>,
+  /// The transition the listener has yet to be told about.
+  changed: Option<Circuit>
+}
+
+impl<T, E> Breaker<T, E> {
+  pub(crate) fn new() -> Breaker<T, E> {
+    Breaker {
+      circuit: Circuit::Closed,
+      failures: 0,
+      opened: None,
+      error: None,
+      changed: None
+    }
+  }
+
+  /// Return the error to fail a run with, if the circuit is open.
+  /// Half-opens the circuit once the cool-down is over.
+  pub(crate) fn check(
+    &mut self,
+    thunky: &Thunky<T, E>
+  ) -> Option<Arc<Result<T, E>>> {
+    let breaker = thunky.options.circuit_breaker.as_ref()?;
+    if self.circuit != Circuit::Open {
+      return None;
+    }
+
+    // A cool-down too long to be added to the opening is never over.
+    let opened = self.opened.unwrap();
+    let over = opened
+      .checked_add(breaker.cool_down)
+      .is_some_and(|over| thunky.options.clock.now() >= over);
+    if !over {
+      return self.error.clone();
+    }
+    self.set(Circuit::HalfOpen);
+    None
+  }
+
+  /// Count the result of a run, which `failed` or not.
+  pub(crate) fn record(&mut self, thunky: &Thunky<T, E>, failed: bool) {
+    let breaker = match &thunky.options.circuit_breaker {
+      Some(breaker) => breaker,
+      None => return
+    };
+
+    if !failed {
+      self.failures = 0;
+      self.set(Circuit::Closed);
+      return;
+    }
+
+    self.failures += 1;
+    if self.circuit == Circuit::HalfOpen || self.failures >= breaker.threshold
+    {
+      if self.error.is_none() {
+        self.error = Some(Arc::new(Err((thunky.error)(Error::CircuitOpen))));
+      }
+      self.opened = Some(thunky.options.clock.now());
+      self.set(Circuit::Open);
+    }
+  }
+
+  fn set(&mut self, circuit: Circuit) {
+    if self.circuit != circuit {
+      self.circuit = circuit;
+      self.changed = Some(circuit);
+    }
+  }
+
+  /// Return the transition to tell the listener about, if there's one.
+  pub(crate) fn changed(
+    &mut self,
+    thunky: &Thunky<T, E>
+  ) -> Option<(Listener, Circuit)> {
+    let changed = self.changed.take()?;
+    let listener = thunky.options.circuit_breaker.as_ref()?.listener.clone()?;
+    Some((listener, changed))
+  }
+}
@@ -0,0 +1,101 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::timer::{Timer, TimerCb};
+
+/// Source of the current time, used to expire cached values.
+pub trait Clock: Send + Sync {
+  fn now(&self) -> Instant;
+}
+
+/// The clock of the system, `Instant::now()`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+}
+
+/// A clock which only moves when told to, for testing expiry without
+/// sleeping.
+///
+/// It's also a `Timer`, whose callbacks are called as the clock moves past
+/// their delay.
+pub struct ManualClock {
+  base: Instant,
+  elapsed: Mutex<Duration>,
+  /// Callbacks, along with the `elapsed` they are due at.
+  timers: Mutex<Vec<(Duration, TimerCb)>>
+}
+
+impl ManualClock {
+  pub fn new() -> ManualClock {
+    ManualClock {
+      base: Instant::now(),
+      elapsed: Mutex::new(Duration::from_secs(0)),
+      timers: Mutex::new(Vec::new())
+    }
+  }
+
+  /// Move the clock forward by `duration`, calling the timer callbacks which
+  /// are due, in order.
+  pub fn advance(&self, duration: Duration) {
+    let elapsed = {
+      let mut elapsed = self.elapsed.lock().unwrap();
+      *elapsed += duration;
+      *elapsed
+    };
+
+    loop {
+      let due = {
+        let mut timers = self.timers.lock().unwrap();
+        let next = timers
+          .iter()
+          .enumerate()
+          .filter(|(_, (due, _))| *due <= elapsed)
+          .min_by_key(|(_, (due, _))| *due)
+          .map(|(index, _)| index);
+        next.map(|index| timers.remove(index))
+      };
+
+      match due {
+        Some((_, callback)) => callback(),
+        None => break
+      }
+    }
+  }
+}
+
+impl fmt::Debug for ManualClock {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("ManualClock")
+      .field("base", &self.base)
+      .field("elapsed", &*self.elapsed.lock().unwrap())
+      .field("timers", &self.timers.lock().unwrap().len())
+      .finish()
+  }
+}
+
+impl Default for ManualClock {
+  fn default() -> ManualClock {
+    ManualClock::new()
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    self.base + *self.elapsed.lock().unwrap()
+  }
+}
+
+impl Timer for ManualClock {
+  fn schedule(&self, delay: Duration, callback: TimerCb) {
+    let elapsed = self.elapsed.lock().unwrap();
+    if let Some(at) = elapsed.checked_add(delay) {
+      self.timers.lock().unwrap().push((at, callback));
+    }
+  }
+}
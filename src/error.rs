@@ -0,0 +1,54 @@
+use std::fmt;
+
+/// Errors produced by thunky itself, delivered to callbacks through the
+/// thunky's error type `E`, which has to implement `From<Error>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Error {
+  /// The `Resolver` of a run was dropped without being resolved.
+  Dropped,
+  /// The circuit breaker of the thunky is open, so the run function was not
+  /// called.
+  CircuitOpen,
+  /// The run, or a callback waiting for it, timed out.
+  Timeout,
+  /// The run was cancelled, see `Resolver::cancelled()`.
+  Cancelled
+}
+
+impl Error {
+  fn as_str(&self) -> &'static str {
+    match self {
+      Error::Dropped => "thunky resolver dropped without a result",
+      Error::CircuitOpen => "thunky circuit breaker is open",
+      Error::Timeout => "thunky timed out",
+      Error::Cancelled => "thunky run was cancelled"
+    }
+  }
+}
+
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(self.as_str())
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for &'static str {
+  fn from(error: Error) -> &'static str {
+    error.as_str()
+  }
+}
+
+impl From<Error> for String {
+  fn from(error: Error) -> String {
+    error.to_string()
+  }
+}
+
+impl From<Error> for std::io::Error {
+  fn from(error: Error) -> std::io::Error {
+    std::io::Error::other(error)
+  }
+}
@@ -0,0 +1,172 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::state::Waiter;
+use crate::{Shared, Thunky};
+
+/// Future returned by [`Thunky::get`].
+///
+/// On first poll it registers a callback on the thunky, like `thunk.run()`
+/// does, and resolves with a clone of the result the thunky is resolved with.
+pub struct Get<'a, T, E> {
+  waiting: Waiting<'a, T, E, Result<T, E>>
+}
+
+impl<'a, T, E> Get<'a, T, E> {
+  pub(crate) fn new(
+    thunky: &'a Thunky<T, E>,
+    timeout: Option<Duration>
+  ) -> Get<'a, T, E> {
+    Get {
+      waiting: Waiting::new(thunky, timeout)
+    }
+  }
+}
+
+impl<'a, T, E> Future for Get<'a, T, E>
+where
+  T: Clone + Send + 'static,
+  E: Clone + Send + 'static
+{
+  type Output = Result<T, E>;
+
+  fn poll(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<Self::Output> {
+    let ready = |result: &Arc<Result<T, E>>| (**result).clone();
+    self.waiting.poll(cx, ready, |slot| {
+      let callback = move |arg: &Result<T, E>| fill(&slot, arg.clone());
+      Waiter::new(Box::new(callback), 0)
+    })
+  }
+}
+
+/// Future returned by [`Thunky::get_shared`].
+///
+/// Like [`Get`], but it resolves with the result itself, shared with the
+/// cache, so `T` and `E` need not be `Clone`.
+pub struct GetShared<'a, T, E> {
+  waiting: Waiting<'a, T, E, Shared<T, E>>
+}
+
+impl<'a, T, E> GetShared<'a, T, E> {
+  pub(crate) fn new(
+    thunky: &'a Thunky<T, E>,
+    timeout: Option<Duration>
+  ) -> GetShared<'a, T, E> {
+    GetShared {
+      waiting: Waiting::new(thunky, timeout)
+    }
+  }
+}
+
+impl<'a, T, E> Future for GetShared<'a, T, E>
+where
+  T: Send + Sync + 'static,
+  E: Send + Sync + 'static
+{
+  type Output = Shared<T, E>;
+
+  fn poll(
+    mut self: Pin<&mut Self>,
+    cx: &mut Context<'_>
+  ) -> Poll<Self::Output> {
+    let ready = |result: &Arc<Result<T, E>>| Shared::new(Arc::clone(result));
+    self.waiting.poll(cx, ready, |slot| {
+      Waiter::shared(Box::new(move |result| fill(&slot, result)))
+    })
+  }
+}
+
+type Slot<O> = Arc<Mutex<Filled<O>>>;
+
+struct Filled<O> {
+  output: Option<O>,
+  waker: Option<Waker>
+}
+
+/// Store `output` in `slot`, and wake the future waiting for it.
+fn fill<O>(slot: &Slot<O>, output: O) {
+  let waker = {
+    let mut slot = slot.lock().unwrap();
+    slot.output = Some(output);
+    slot.waker.take()
+  };
+  if let Some(waker) = waker {
+    waker.wake();
+  }
+}
+
+/// What `Get` and `GetShared` have in common: a callback queued on the
+/// thunky, which fills a slot with an `O` made from the result.
+struct Waiting<'a, T, E, O> {
+  thunky: &'a Thunky<T, E>,
+  timeout: Option<Duration>,
+  /// The waiter id of the callback, while it's queued.
+  waiter: Option<u64>,
+  slot: Option<Slot<O>>
+}
+
+impl<'a, T, E, O> Waiting<'a, T, E, O> {
+  fn new(
+    thunky: &'a Thunky<T, E>,
+    timeout: Option<Duration>
+  ) -> Waiting<'a, T, E, O> {
+    Waiting {
+      thunky,
+      timeout,
+      waiter: None,
+      slot: None
+    }
+  }
+
+  /// On first poll, make the output right away with `ready` if the thunky is
+  /// resolved, or queue the waiter `waiter` makes for the slot.
+  fn poll<R, W>(&mut self, cx: &mut Context<'_>, ready: R, waiter: W) -> Poll<O>
+  where
+    R: FnOnce(&Arc<Result<T, E>>) -> O,
+    W: FnOnce(Slot<O>) -> Waiter<T, E>
+  {
+    let slot = match &self.slot {
+      Some(slot) => Arc::clone(slot),
+      None => {
+        if let Some(result) = self.thunky.ready() {
+          return Poll::Ready(ready(&result));
+        }
+
+        let slot = Arc::new(Mutex::new(Filled {
+          output: None,
+          waker: Some(cx.waker().clone())
+        }));
+        self.slot = Some(Arc::clone(&slot));
+        let waiter = waiter(Arc::clone(&slot));
+        self.waiter = self.thunky.enqueue(waiter, self.timeout);
+        slot
+      }
+    };
+
+    let mut slot = slot.lock().unwrap();
+    match slot.output.take() {
+      Some(output) => {
+        self.waiter = None;
+        Poll::Ready(output)
+      },
+      None => {
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+impl<'a, T, E, O> Drop for Waiting<'a, T, E, O> {
+  fn drop(&mut self) {
+    if let Some(waiter) = self.waiter.take() {
+      self.thunky.withdraw(waiter);
+    }
+  }
+}
@@ -0,0 +1,106 @@
+use std::ptr;
+
+use crate::state::Waiter;
+use crate::sync::{AtomicPtr, AtomicU64, Ordering};
+
+/// A waiter pushed onto the inbox, linked to the one pushed before it.
+struct Node<T, E> {
+  waiter: Waiter<T, E>,
+  next: *mut Node<T, E>
+}
+
+/// Waiters queued without the mutex of the thunky, while `run()` would only
+/// queue them.
+///
+/// It's a stack of waiters, pushed with a compare and swap. Whoever locks the
+/// mutex closes it and takes its waiters, in the order they were pushed, so
+/// that the queue under the mutex has every waiter while it's locked. It's
+/// opened again on unlock if the state only queues waiters, and `push()`
+/// fails while it's closed, leaving the waiter to the state.
+pub(crate) struct Inbox<T, E> {
+  head: AtomicPtr<Node<T, E>>,
+  /// The id of the last waiter, queued under the mutex or not.
+  ids: AtomicU64
+}
+
+unsafe impl<T, E> Send for Inbox<T, E> where Waiter<T, E>: Send {}
+unsafe impl<T, E> Sync for Inbox<T, E> where Waiter<T, E>: Send {}
+
+impl<T, E> Inbox<T, E> {
+  pub(crate) fn new() -> Inbox<T, E> {
+    Inbox {
+      head: AtomicPtr::new(closed()),
+      ids: AtomicU64::new(0)
+    }
+  }
+
+  /// Return a new waiter id.
+  pub(crate) fn id(&self) -> u64 {
+    self.ids.fetch_add(1, Ordering::Relaxed) + 1
+  }
+
+  /// Queue `waiter`, or give it back if the inbox is closed.
+  pub(crate) fn push(&self, waiter: Waiter<T, E>) -> Result<(), Waiter<T, E>> {
+    let mut head = self.head.load(Ordering::Relaxed);
+    if head == closed() {
+      return Err(waiter);
+    }
+
+    let node = Box::into_raw(Box::new(Node {
+      waiter,
+      next: head
+    }));
+    loop {
+      // Pairs with the swap of `close()`, which takes the node.
+      match self.head.compare_exchange_weak(
+        head,
+        node,
+        Ordering::Release,
+        Ordering::Relaxed
+      ) {
+        Ok(_) => return Ok(()),
+        Err(current) if current == closed() => {
+          // The node was never shared.
+          let node = unsafe { Box::from_raw(node) };
+          return Err(node.waiter);
+        },
+        Err(current) => {
+          head = current;
+          unsafe { (*node).next = head };
+        }
+      }
+    }
+  }
+
+  /// Close the inbox, and move its waiters to the end of `queue`, in the
+  /// order they were pushed. Must be called with the mutex of the thunky
+  /// held.
+  pub(crate) fn close(&self, queue: &mut Vec<Waiter<T, E>>) {
+    let mut head = self.head.swap(closed(), Ordering::Acquire);
+    let start = queue.len();
+    while !head.is_null() && head != closed() {
+      let node = unsafe { Box::from_raw(head) };
+      head = node.next;
+      queue.push(node.waiter);
+    }
+    queue[start..].reverse();
+  }
+
+  /// Open the inbox, closed by `close()`. Must be called with the mutex of
+  /// the thunky held.
+  pub(crate) fn open(&self) {
+    // Nothing is pushed while it's closed, so there's nothing to lose.
+    self.head.store(ptr::null_mut(), Ordering::Relaxed);
+  }
+}
+
+impl<T, E> Drop for Inbox<T, E> {
+  fn drop(&mut self) {
+    self.close(&mut Vec::new());
+  }
+}
+
+/// The head of a closed inbox, which is never a node.
+fn closed<T, E>() -> *mut Node<T, E> {
+  ptr::NonNull::dangling().as_ptr()
+}
@@ -1,21 +1,96 @@
 #![cfg_attr(test, deny(warnings))]
 
-use std::sync::{Arc, Mutex};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
 
-type Cb<T, E> = Box<Fn(&Result<T, E>) -> () + Send + Sync>;
-type RunCb<T, E> = Box<Fn(&Thunky<T, E>) -> () + Send + Sync>;
+mod builder;
+mod cancel;
+mod circuit;
+mod clock;
+mod error;
+mod future;
+mod inbox;
+mod map;
+mod policy;
+mod ready;
+mod resolver;
+mod retry;
+mod shared;
+mod state;
+mod sync;
+mod task;
+mod timer;
+mod weigher;
+
+pub mod rt;
+
+pub use crate::builder::ThunkyBuilder;
+pub use crate::cancel::{Cancellation, Cancelled};
+pub use crate::circuit::{Circuit, CircuitBreaker};
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+pub use crate::error::Error;
+pub use crate::future::{Get, GetShared};
+pub use crate::map::{Eviction, MapStats, ThunkyMap};
+pub use crate::policy::{CachePolicy, Decision, DefaultPolicy};
+pub use crate::resolver::Resolver;
+pub use crate::retry::Retry;
+pub use crate::rt::Runtime;
+pub use crate::shared::Shared;
+pub use crate::state::{Generation, Status};
+pub use crate::timer::{RuntimeTimer, ThreadTimer, Timer, TimerCb};
+pub use crate::weigher::Weigher;
+
+use crate::builder::Options;
+use crate::inbox::Inbox;
+use crate::ready::{Locked, Ready};
+use crate::state::{AnyState, Callback, Inner, Run, Settled, State, Step};
+use crate::state::{Wait, Waiter};
+use crate::sync::Mutex;
+
+type Cb<T, E> = Box<dyn FnOnce(&Result<T, E>) + Send>;
+type SharedCb<T, E> = Box<dyn FnOnce(Shared<T, E>) + Send>;
+type RunCb<T, E> = Box<dyn Fn(Resolver<T, E>) + Send + Sync>;
+type MapRun<K, T, E> = dyn Fn(&K, Resolver<T, E>) + Send + Sync;
+type MapRunCb<K, T, E> = Box<MapRun<K, T, E>>;
+type SettledCb<T, E> = Box<dyn Fn(&Thunky<T, E>) + Send + Sync>;
 
 pub struct Thunky<T, E> {
   run: RunCb<T, E>,
-  state: Mutex<Option<Box<State<T, E> + Send + Sync>>>,
-  stack: Mutex<Vec<Cb<T, E>>>,  
-  cache: Mutex<Option<Result<T, E>>>
+  options: Options,
+  policy: Arc<dyn CachePolicy<T, E>>,
+  inner: Mutex<Inner<T, E>>,
+  /// The cached value while in `Finish`, served without locking `inner`.
+  ready: Ready<T, E>,
+  /// Waiters queued without locking `inner` while in `Wait` or `Backoff`.
+  inbox: Inbox<T, E>,
+  this: Weak<Thunky<T, E>>,
+  error: fn(Error) -> E,
+  /// `Thunky::schedule()`, which needs bounds `resolve()` doesn't have.
+  defer: fn(&Thunky<T, E>, Duration, Alarm),
+  /// Called once a run is settled and the thunky unlocked, for a thunky map
+  /// to weigh the result, see `ThunkyMap::entry()`.
+  pub(crate) settled: Option<SettledCb<T, E>>
+}
+
+/// What a timer of a thunky fires for.
+enum Alarm {
+  /// Retry the failed run of the generation, see `ThunkyBuilder::retry()`.
+  Retry(Generation),
+  /// Time out the run, see `ThunkyBuilder::init_timeout()`.
+  Run(u64),
+  /// Time out the waiter, see `Thunky::run_with_timeout()`.
+  Waiter(u64)
 }
 
 impl<T, E> Thunky<T, E> {
-  /// Create a thunky instance with a function which take a reference of thunky as parameters,
+  /// Create a thunky instance with a run function, which takes a `Resolver`
+  /// as parameter.
   ///
-  /// So we can call `thunky.cache()` in this function.    
+  /// So we can call `resolver.resolve()` in this function, or move the resolver
+  /// somewhere else and resolve it later. If the resolver is dropped instead,
+  /// the callbacks get `Error::Dropped`.
   /// # Examples
   ///  
   /// ```
@@ -26,37 +101,234 @@ impl<T, E> Thunky<T, E> {
   ///
   /// let v = Mutex::new(0);
   ///
-  /// let run = move |thunk: &Thunky<u32, &str>| {
+  /// let run = move |resolver: Resolver<u32, &str>| {
   ///   *v.lock().unwrap() += 1;
-  ///   thunk.cache(Ok(*v.lock().unwrap()));
+  ///   resolver.resolve(Ok(*v.lock().unwrap()));
   /// };
   ///
-  /// let thunk = Thunky::new(Box::new(run));
+  /// let thunk = Thunky::new(run);
   /// 
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| {
   ///   assert_eq!(1, arg.unwrap());
-  /// }));
+  /// });
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| {
   ///   assert_eq!(1, arg.unwrap());
-  /// }));  
+  /// });  
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| {
   ///   assert_eq!(1, arg.unwrap());
-  /// }));
+  /// });
   /// ```  
-  pub fn new (run: RunCb<T, E>) -> Arc<Thunky<T, E>> {
-    Arc::new(Thunky {
+  pub fn new<F>(run: F) -> Arc<Thunky<T, E>>
+  where
+    F: Fn(Resolver<T, E>) + Send + Sync + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    ThunkyBuilder::new().build(run)
+  }
+
+  fn with_run(
+    run: RunCb<T, E>,
+    options: Options,
+    policy: Arc<dyn CachePolicy<T, E>>,
+    error: fn(Error) -> E,
+    this: &Weak<Thunky<T, E>>
+  ) -> Thunky<T, E>
+  where
+    T: Send + Sync + 'static,
+    E: Send + Sync + 'static
+  {
+    Thunky {
       run,
-      state: Mutex::new(Some(Box::new(Run {}))),      
-      stack: Mutex::new(Vec::new()),      
-      cache: Mutex::new(None)
-    })
+      options,
+      policy,
+      inner: Mutex::new(Inner::new()),
+      ready: Ready::new(),
+      inbox: Inbox::new(),
+      this: Weak::clone(this),
+      error,
+      defer: Thunky::schedule,
+      settled: None
+    }
+  }
+
+  /// Lock the inner state. The cached value is published for `ready()` as
+  /// the state leaves it when it's unlocked.
+  fn lock(&self) -> Locked<'_, T, E> {
+    Locked::new(&self.ready, &self.inbox, self.inner.lock().unwrap())
+  }
+
+  /// Return the cached result if it's resolved and not expired, without
+  /// locking: the fast path of `run()` on a thunky in `Finish`.
+  pub(crate) fn ready(&self) -> Option<Arc<Result<T, E>>> {
+    match self.ready.get()? {
+      (_, Some(expires)) if self.options.clock.now() >= expires => None,
+      (result, _) => Some(result)
+    }
+  }
+
+  /// Call the run function for `generation`.
+  fn init(&self, generation: Generation) {
+    let (run, token) = {
+      let mut inner = self.lock();
+      inner.runs += 1;
+      inner.attempts = inner.retries + 1;
+      inner.failing = false;
+      (inner.runs, inner.token(generation))
+    };
+    if let Some(timeout) = self.options.init_timeout {
+      (self.defer)(self, timeout, Alarm::Run(run));
+    }
+    (self.run)(Resolver::new(Weak::clone(&self.this), generation, token))
+  }
+
+  /// Create a thunky instance from an async function.
+  ///
+  /// When the thunky is idle, `init` is called and the returned future is
+  /// polled until completion; its output resolves the run, so there is no way
+  /// to forget resolving it. The future is driven by its own wake-ups and
+  /// does not need an executor: it's first polled by the caller of `run()`,
+  /// and once woken on the worker threads of `ThreadTimer`, never on the
+  /// waking thread. Use `Thunky::from_async_on()` to poll it on an executor
+  /// instead.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: from_async runs only once
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use std::sync::Arc;
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use futures::executor::block_on;
+  /// use thunky::*;
+  ///
+  /// let calls = Arc::new(AtomicUsize::new(0));
+  ///
+  /// let calls_clone = Arc::clone(&calls);
+  /// let thunk = Thunky::from_async(move || {
+  ///   let calls = Arc::clone(&calls_clone);
+  ///   async move {
+  ///     Ok::<usize, &str>(calls.fetch_add(1, Ordering::SeqCst) + 1)
+  ///   }
+  /// });
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// assert_eq!(Ok(1), block_on(thunk.get()));
+  /// assert_eq!(1, calls.load(Ordering::SeqCst));
+  /// ```
+  ///
+  /// The future may also complete later, woken from another thread:
+  ///
+  /// ```
+  /// // test: from_async resolves async
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use std::sync::Mutex;
+  /// use std::thread;
+  /// use std::time::Duration;
+  /// use futures::channel::oneshot;
+  /// use futures::executor::block_on;
+  /// use thunky::*;
+  ///
+  /// let (tx, rx) = oneshot::channel::<u32>();
+  /// let rx = Mutex::new(Some(rx));
+  ///
+  /// let thunk = Thunky::from_async(move || {
+  ///   let rx = rx.lock().unwrap().take().unwrap();
+  ///   async move { rx.await.map_err(|_| "canceled") }
+  /// });
+  ///
+  /// thread::spawn(move || {
+  ///   thread::sleep(Duration::from_millis(100));
+  ///   tx.send(1).unwrap();
+  /// });
+  ///
+  /// assert_eq!(Ok(1), block_on(thunk.get()));
+  /// ```
+  pub fn from_async<F, Fut>(init: F) -> Arc<Thunky<T, E>>
+  where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    ThunkyBuilder::new().build_async(init)
+  }
+
+  /// Create a thunky instance from an async function whose future is spawned
+  /// on `runtime`.
+  ///
+  /// Works like `Thunky::from_async()`, except that the future is driven by
+  /// the runtime's executor instead of by its own wake-ups. With one of the
+  /// `rt-tokio`, `rt-async-std` or `rt-smol` features enabled, the runtimes in
+  /// `thunky::rt` can be used here.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: from_async_on spawns on runtime
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use std::thread;
+  /// use std::time::Duration;
+  /// use futures::executor::block_on;
+  /// use thunky::rt::{BoxFuture, Runtime};
+  /// use thunky::*;
+  ///
+  /// struct Threads;
+  ///
+  /// impl Runtime for Threads {
+  ///   fn spawn(&self, future: BoxFuture<()>) {
+  ///     thread::spawn(move || block_on(future));
+  ///   }
+  ///
+  ///   fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+  ///     Box::pin(async move { thread::sleep(duration) })
+  ///   }
+  /// }
+  ///
+  /// let thunk = Thunky::from_async_on(Threads, || {
+  ///   let sleep = Threads.sleep(Duration::from_millis(100));
+  ///   async move {
+  ///     sleep.await;
+  ///     Ok::<u32, &str>(1)
+  ///   }
+  /// });
+  ///
+  /// assert_eq!(Ok(1), block_on(thunk.get()));
+  /// ```
+  pub fn from_async_on<R, F, Fut>(runtime: R, init: F) -> Arc<Thunky<T, E>>
+  where
+    R: Runtime,
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<T, E>> + Send + 'static,
+    T: Send + Sync + 'static,
+    E: From<Error> + Send + Sync + 'static
+  {
+    ThunkyBuilder::new().build_async_on(runtime, init)
   }
 
   /// Set cache, if incoming is `Ok(T)`, cahce will be preserved, and ignore the following `thunk.cache()`.
   ///
   /// otherwise cache will be reset by next calling to `thunk.cache()`
+  ///
+  /// A policy set with `ThunkyBuilder::policy()` decides which results are
+  /// cached instead.
+  ///
+  /// `resolver.resolve()` does the same for the run of its resolver; call this
+  /// to set the cache from outside of a run.
   /// 
   /// # Examples
   ///
@@ -70,73 +342,404 @@ impl<T, E> Thunky<T, E> {
   ///
   /// let v = Mutex::new(0);
   ///
-  /// let run = move |thunk: &Thunky<u32, &str>| {
+  /// let run = move |resolver: Resolver<u32, &str>| {
   ///   *v.lock().unwrap() += 1;
   ///
   ///   if *v.lock().unwrap() == 1 {
-  ///     thunk.cache(Err("stop"))
+  ///     resolver.resolve(Err("stop"))
   ///   } else if *v.lock().unwrap() == 2 {
-  ///     thunk.cache(Err("stop"))
+  ///     resolver.resolve(Err("stop"))
   ///   } else if *v.lock().unwrap() == 3 {
-  ///     thunk.cache(Ok(*v.lock().unwrap()))
+  ///     resolver.resolve(Ok(*v.lock().unwrap()))
   ///   } else if *v.lock().unwrap() == 4 {
-  ///     thunk.cache(Ok(*v.lock().unwrap()))
+  ///     resolver.resolve(Ok(*v.lock().unwrap()))
   ///   }
   /// };
   ///
-  /// let thunk = Thunky::new(Box::new(run));
+  /// let thunk = Thunky::new(run);
   /// 
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!("stop", arg.unwrap_err());
-  /// }));
+  /// });
   /// 
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!("stop", arg.unwrap_err());
-  /// }));  
+  /// });  
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!(3, arg.unwrap());
-  /// }));    
+  /// });    
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!(3, arg.unwrap());
-  /// }));  
+  /// });  
   /// ```
-  pub fn cache(&self, a: Result<T, E>) -> () {    
-    while self.stack.lock().unwrap().len() > 0 {
-      let cb = self.stack.lock().unwrap().pop().unwrap();
-      cb(&a);
+  pub fn cache(&self, a: Result<T, E>) {
+    self.resolve(None, a)
+  }
+
+  /// Set cache like `thunky.cache()`, unless `generation` was superseded by
+  /// `thunky.reset()`, in which case `a` is dropped.
+  ///
+  /// This is what `resolver.resolve()` does with the generation of its run.
+  pub fn cache_for(&self, generation: Generation, a: Result<T, E>) {
+    self.resolve(Some(generation), a)
+  }
+
+  fn resolve(&self, generation: Option<Generation>, a: Result<T, E>) {
+    let inner = self.lock();
+    if generation.is_some_and(|g| g != inner.generation) {
+      return;
     }
+    self.settle(inner, a)
+  }
 
-    #[allow(unused_assignments)]
-    let mut is_cached = false;
+  /// Settle the current state with `a`, then unlock and deliver it.
+  fn settle(&self, mut inner: Locked<'_, T, E>, a: Result<T, E>) {
+    let state = inner.state.take().unwrap();
+    let settled = state.cache(self, &mut inner, a);
+    let queue = match settled {
+      Settled::Deliver(_) => inner.take_queue(),
+      Settled::Retry(_) => Vec::new()
+    };
+    let generation = inner.generation;
+    let changed = inner.breaker.changed(self);
+    drop(inner);
 
-    match self.cache.lock().unwrap().as_ref() {
-      Some(v) => {
-        if v.is_ok() {
-          is_cached = true
-        } else {
-          is_cached = false
+    if let Some((listener, circuit)) = changed {
+      listener(circuit);
+    }
+
+    match settled {
+      Settled::Deliver(result) => {
+        if let Some(settled) = &self.settled {
+          settled(self);
+        }
+        for waiter in queue {
+          waiter.callback.call(&result);
         }
       },
-      None => {
-        is_cached = false
+      Settled::Retry(delay) => {
+        (self.defer)(self, delay, Alarm::Retry(generation))
+      }
+    }
+  }
+
+  /// Fire `alarm` after `delay` on the timer of the thunky.
+  fn schedule(&self, delay: Duration, alarm: Alarm)
+  where
+    T: Send + Sync + 'static,
+    E: Send + Sync + 'static
+  {
+    let this = Weak::clone(&self.this);
+    self.options.timer.schedule(delay, Box::new(move || {
+      if let Some(thunky) = this.upgrade() {
+        thunky.fire(alarm);
+      }
+    }));
+  }
+
+  fn fire(&self, alarm: Alarm) {
+    match alarm {
+      Alarm::Retry(generation) => self.retry(generation),
+      Alarm::Run(run) => self.time_out_run(run),
+      Alarm::Waiter(waiter) => self.time_out_waiter(waiter)
+    }
+  }
+
+  /// Fail the run `run` with `Error::Timeout` if it's still waiting for its
+  /// resolver, which is ignored from then on. A refresh fails like any other
+  /// run, keeping the stale value while it's servable.
+  fn time_out_run(&self, run: u64) {
+    let mut inner = self.lock();
+    let state = inner.state.as_ref().unwrap();
+    let waiting = state.is_running() && !state.is_backing_off();
+    if inner.runs != run || !waiting {
+      return;
+    }
+
+    let token = inner.supersede();
+    self.settle(inner, Err((self.error)(Error::Timeout)));
+    token.cancel();
+  }
+
+  /// Call the callback of `waiter` with `Error::Timeout`, if it's still
+  /// waiting.
+  fn time_out_waiter(&self, waiter: u64) {
+    if let Some(callback) = self.withdraw(waiter) {
+      callback.call(&Arc::new(Err((self.error)(Error::Timeout))));
+    }
+  }
+
+  /// Take the callback of `waiter` out of the queue, if it's still waiting.
+  ///
+  /// With `Cancellation::LastWaiter`, a run left without waiters is
+  /// superseded and the thunky goes back to `Run`.
+  pub(crate) fn withdraw(&self, waiter: u64) -> Option<Callback<T, E>> {
+    let (callback, token) = {
+      let mut inner = self.lock();
+      let index = inner.queue.iter().position(|queued| queued.id == waiter)?;
+      let callback = inner.queue.remove(index).callback;
+
+      let state = inner.state.as_ref().unwrap();
+      let cancel = self.options.cancellation == Cancellation::LastWaiter
+        && inner.queue.is_empty()
+        && state.is_running()
+        && inner.cache.is_none();
+      let token = if cancel {
+        inner.retries = 0;
+        inner.state = Some(AnyState::Run(Run {}));
+        Some(inner.supersede())
+      } else {
+        None
+      };
+      (callback, token)
+    };
+
+    if let Some(token) = token {
+      token.cancel();
+    }
+    Some(callback)
+  }
+
+  fn retry(&self, generation: Generation) {
+    let rerun = {
+      let mut inner = self.lock();
+      let rerun = inner.generation == generation
+        && inner.state.as_ref().unwrap().is_backing_off();
+      if rerun {
+        inner.state = Some(AnyState::Wait(Wait {}));
       }
+      rerun
+    };
+
+    if rerun {
+      self.init(generation);
+    }
+  }
+
+  /// Whether the run function was called and its run is not resolved yet.
+  pub(crate) fn is_running(&self) -> bool {
+    self.ready.is_running()
+  }
+
+  /// Return when the cached result expires, if it's served without the
+  /// mutex.
+  pub(crate) fn expires(&self) -> Option<Instant> {
+    self.ready.get()?.1
+  }
+
+  /// Return the cached result, if `run()` would deliver it right away.
+  pub(crate) fn peek(&self) -> Option<Arc<Result<T, E>>> {
+    if let Some(result) = self.ready() {
+      return Some(result);
+    }
+    let inner = self.lock();
+    inner.state.as_ref().unwrap().peek(self, &inner)
+  }
+
+  /// Return the current generation of the thunky.
+  ///
+  /// The generation changes on every `thunky.reset()`, and when a run times
+  /// out, so a run function can tell if the run it was called for has been
+  /// superseded.
+  pub fn generation(&self) -> Generation {
+    self.lock().generation
+  }
+
+  /// Return the state of the circuit breaker, see
+  /// `ThunkyBuilder::circuit_breaker()`. Always `Circuit::Closed` without one.
+  ///
+  /// An open circuit only half-opens on the first `thunky.run()` after the
+  /// cool-down, it stays `Circuit::Open` until then.
+  pub fn circuit(&self) -> Circuit {
+    self.lock().breaker.circuit
+  }
+
+  /// Return what the thunky is doing, without calling the run function or
+  /// waiting for a run.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: status follows the run
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use thunky::*;
+  ///
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let thunk = Thunky::new(move |resolver: Resolver<u32, &'static str>| {
+  ///   resolvers_clone.lock().unwrap().push(resolver);
+  /// });
+  /// assert_eq!(Status::Idle, thunk.status());
+  ///
+  /// thunk.run(|_: &Result<u32, &str>| {});
+  /// assert_eq!(Status::Initializing, thunk.status());
+  /// assert_eq!(1, thunk.waiter_count());
+  ///
+  /// resolvers.lock().unwrap().pop().unwrap().resolve(Err("down"));
+  /// assert_eq!(Status::Failed, thunk.status());
+  /// assert!(thunk.failed_at().is_some());
+  ///
+  /// thunk.run(|_: &Result<u32, &str>| {});
+  /// resolvers.lock().unwrap().pop().unwrap().resolve(Ok(1));
+  /// assert_eq!(Status::Resolved, thunk.status());
+  /// assert_eq!(0, thunk.waiter_count());
+  /// assert!(thunk.resolved_at().is_some());
+  /// ```
+  pub fn status(&self) -> Status {
+    if self.ready().is_some() {
+      return Status::Resolved;
     }
+    let inner = self.lock();
+    inner.state.as_ref().unwrap().status(self, &inner)
+  }
+
+  /// Return how many callbacks and futures are waiting for the run.
+  pub fn waiter_count(&self) -> usize {
+    self.lock().queue.len()
+  }
+
+  /// Return how many times the run function was called for the run in
+  /// progress, or for the last one, retries included. `0` until the run
+  /// function is called.
+  pub fn attempts(&self) -> u32 {
+    self.lock().attempts
+  }
+
+  /// Return when a run last resolved with `Ok(T)`, on the clock of the
+  /// thunky, whether it was cached or not.
+  pub fn resolved_at(&self) -> Option<Instant> {
+    self.lock().resolved_at
+  }
+
+  /// Return when a run last resolved with `Err(E)`, on the clock of the
+  /// thunky. Every failed attempt counts, retried or not.
+  pub fn failed_at(&self) -> Option<Instant> {
+    self.lock().failed_at
+  }
+
+  /// Drop the cached value, so the next `thunky.run()` calls the run function
+  /// again.
+  ///
+  /// A run in progress is not affected, its result is still cached.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: invalidate re-runs
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::atomic::{AtomicUsize, Ordering};
+  /// use thunky::*;
+  ///
+  /// let v = AtomicUsize::new(0);
+  ///
+  /// let thunk = Thunky::new(move |resolver: Resolver<usize, &str>| {
+  ///   resolver.resolve(Ok(v.fetch_add(1, Ordering::SeqCst) + 1));
+  /// });
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(1, arg.unwrap());
+  /// });
+  ///
+  /// thunk.invalidate();
+  ///
+  /// thunk.run(|arg: &Result<usize, &str>| {
+  ///   assert_eq!(2, arg.unwrap());
+  /// });
+  /// ```
+  pub fn invalidate(&self) {
+    let mut inner = self.lock();
+    let state = inner.state.take().unwrap();
+    state.invalidate(&mut inner);
+  }
 
-    if !is_cached {
-      *self.cache.lock().unwrap() = Some(a);
+  /// Drop the cached value and supersede the run in progress.
+  ///
+  /// The generation changes, so a late `resolver.resolve()` from the run in
+  /// progress is ignored instead of overwriting a newer value. Callbacks
+  /// waiting for it are carried over to a new run, which is started right
+  /// away.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: late resolve after reset is ignored
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use thunky::*;
+  ///
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let thunk = Thunky::new(move |resolver: Resolver<u32, &'static str>| {
+  ///   resolvers_clone.lock().unwrap().push(resolver);
+  /// });
+  ///
+  /// thunk.run(|arg: &Result<u32, &'static str>| {
+  ///   assert_eq!(2, arg.unwrap());
+  /// });
+  ///
+  /// thunk.reset();
+  ///
+  /// let mut resolvers = resolvers.lock().unwrap();
+  /// let second = resolvers.pop().unwrap();
+  /// let first = resolvers.pop().unwrap();
+  /// assert_ne!(first.generation(), second.generation());
+  ///
+  /// first.resolve(Ok(1));
+  /// second.resolve(Ok(2));
+  /// ```
+  pub fn reset(&self) {
+    let (token, rerun) = self.lock().reset();
+    token.cancel();
+    if let Some(generation) = rerun {
+      self.init(generation);
     }
   }
 
-  /// Call `run()` of the current state of thunky. There're three private inner states in thunky:
+  /// Call `callback` with the result of the run, calling the run function
+  /// first if nothing is cached. What happens depends on the state of the
+  /// thunky, see `thunky.status()`:
+  ///
+  /// - `Status::Idle` or `Status::Failed`: the run function is called, and the
+  ///   callback waits for it, the thunky is `Status::Initializing` meanwhile.
+  /// - `Status::Initializing` or `Status::BackingOff`: the callback waits for
+  ///   the run in progress, failed runs are retried with
+  ///   `ThunkyBuilder::retry()`.
+  /// - `Status::Resolved`: the callback is called right away with the cached
+  ///   result, until the ttl set with `ThunkyBuilder::ttl()` expires.
+  /// - `Status::Expired`: the run function is called again, unless
+  ///   `ThunkyBuilder::stale_while_revalidate()` serves the stale value while
+  ///   it's refreshed, the thunky is `Status::Refreshing` meanwhile.
+  ///
+  /// Whether a result is cached is up to `ThunkyBuilder::policy()`, which
+  /// caches `Ok(T)` only by default.
   ///
-  /// ` Run {} `: initial state, and after set the cache to `Err(E)`, state turns back to `Run`.
+  /// The callback is called once, so it may move things out, like a sender.
+  /// It's only boxed if it has to wait, use `run_boxed()` for a callback
+  /// which is boxed already.
   ///
-  /// ` Wait {} `: after call `run()` of `Run`, before set the cache, state stays `Wait`.
+  /// # Re-entrancy
   ///
-  /// ` Finish {} `: after set the cache to `Ok(T)`, state turns to `Finish` forever.     
+  /// The run function and the callbacks are only called once the internal lock
+  /// of thunky is released, so they may call `run()` and `cache()` on the same
+  /// thunky:
   ///
+  /// - `run()` from the run function finds the thunky initializing, its
+  ///   callback is queued and called by the next `cache()`.
+  /// - `cache()` from the run function resolves all queued callbacks, as usual.
+  /// - callbacks see the state left by the `cache()` calling them: `run()` gets
+  ///   the cached value once resolved, or calls the run function again after
+  ///   an `Err(E)`.
   ///
   /// # Examples
   ///  
@@ -144,93 +747,405 @@ impl<T, E> Thunky<T, E> {
   /// // test: run only once async
   ///
   /// extern crate thunky;
-  /// extern crate tokio;
   ///
   /// use std::sync::{ Arc, Mutex };
-  /// use std::time::{Duration, Instant};
-  /// use tokio::prelude::*;
-  /// use tokio::timer::Delay;
-  /// use thunky::*;  
-  ///  
-  /// let run = move |_thunk: &Thunky<u32, &str>| {};
+  /// use std::thread;
+  /// use std::time::Duration;
+  /// use thunky::*;
   ///
-  /// let thunk = Thunky::new(Box::new(run));
+  /// let v = Arc::new(Mutex::new(0));
+  /// let tasks = Arc::new(Mutex::new(Vec::new()));
   ///
-  /// let v = Mutex::new(0);
-  /// let thunk_clone = Arc::clone(&thunk);
-  /// let when = Instant::now() + Duration::from_millis(1000);    
-  /// let task = Delay::new(when)
-  ///   .and_then(move |_| {
+  /// let tasks_clone = Arc::clone(&tasks);
+  /// let run = move |resolver: Resolver<u32, &'static str>| {
+  ///   let v = Arc::clone(&v);
+  ///   tasks_clone.lock().unwrap().push(thread::spawn(move || {
+  ///     thread::sleep(Duration::from_millis(1000));
   ///     *v.lock().unwrap() += 1;
-  ///     thunk_clone.cache(Ok(*v.lock().unwrap()));
-  ///     Ok(())
-  ///   })
-  ///   .map_err(|e| panic!("delay errored; err={:?}", e));  
+  ///     resolver.resolve(Ok(*v.lock().unwrap()));
+  ///   }));
+  /// };
+  ///
+  /// let thunk = Thunky::new(run);
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!(1, arg.unwrap());
-  /// }));
+  /// });
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_ne!(2, arg.unwrap());
-  /// }));  
+  /// });
   ///
-  /// thunk.run(Box::new(|arg: &Result<u32, &str>| -> () {
+  /// thunk.run(|arg: &Result<u32, &str>| -> () {
   ///   assert_eq!(1, arg.unwrap());
-  /// }));
+  /// });
   ///
-  /// tokio::run(task);  
-  /// ```    
-  pub fn run(&self, callback: Cb<T, E>) -> () {
-    let state = self.state.lock().unwrap().take().unwrap();
-    state.run(self, callback)
+  /// let task = tasks.lock().unwrap().pop().unwrap();
+  /// task.join().unwrap();
+  /// assert!(tasks.lock().unwrap().is_empty());
+  /// ```
+  pub fn run<F>(&self, callback: F)
+  where
+    F: FnOnce(&Result<T, E>) + Send + 'static
+  {
+    if let Some(result) = self.ready() {
+      return callback(&result);
+    }
+    self.enqueue(Waiter::new(Box::new(callback), 0), None);
   }
-}
 
-trait State<T, E> {
-  fn run(&self, thunky: &Thunky<T, E>, callback: Cb<T, E>) -> ();
-}
+  /// Call `run()` with a boxed callback, which is queued as is instead of
+  /// being boxed again.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: run with a sender and a boxed callback
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::mpsc;
+  /// use thunky::*;
+  ///
+  /// let thunk = Thunky::new(|resolver: Resolver<u32, &'static str>| {
+  ///   resolver.resolve(Ok(1))
+  /// });
+  ///
+  /// let (tx, rx) = mpsc::channel();
+  /// thunk.run(move |arg: &Result<u32, &str>| tx.send(*arg).unwrap());
+  /// assert_eq!(Ok(1), rx.recv().unwrap());
+  ///
+  /// let callbacks: Vec<Box<dyn FnOnce(&Result<u32, &str>) + Send>> = vec![
+  ///   Box::new(|arg| assert_eq!(Ok(1), *arg)),
+  ///   Box::new(|arg| assert!(arg.is_ok()))
+  /// ];
+  /// for callback in callbacks {
+  ///   thunk.run_boxed(callback);
+  /// }
+  /// ```
+  pub fn run_boxed(&self, callback: Cb<T, E>) {
+    self.enqueue(Waiter::new(callback, 0), None);
+  }
 
-struct Run {}
+  /// Call `run()` with a priority for the callback.
+  ///
+  /// Waiting callbacks are called in order of priority, highest first, and in
+  /// the order they were queued within the same priority. `run()` queues with
+  /// priority 0. A resolved thunky calls the callback right away, whatever its
+  /// priority.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: priority waiters go first
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use thunky::*;
+  ///
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  /// let order = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let thunk = Thunky::new(move |resolver: Resolver<u32, &'static str>| {
+  ///   resolvers_clone.lock().unwrap().push(resolver);
+  /// });
+  ///
+  /// for (name, priority) in [("first", 0), ("urgent", 10), ("second", 0)] {
+  ///   let order = Arc::clone(&order);
+  ///   thunk.run_with_priority(priority, move |_: &Result<u32, &str>| {
+  ///     order.lock().unwrap().push(name);
+  ///   });
+  /// }
+  ///
+  /// resolvers.lock().unwrap().pop().unwrap().resolve(Ok(1));
+  /// assert_eq!(vec!["urgent", "first", "second"], *order.lock().unwrap());
+  /// ```
+  pub fn run_with_priority<F>(&self, priority: i32, callback: F)
+  where
+    F: FnOnce(&Result<T, E>) + Send + 'static
+  {
+    if let Some(result) = self.ready() {
+      return callback(&result);
+    }
+    self.enqueue(Waiter::new(Box::new(callback), priority), None);
+  }
 
-impl<T, E> State<T, E> for Run {
-  fn run (&self, thunky: &Thunky<T, E>, callback: Cb<T, E>) -> () {
-    thunky.stack.lock().unwrap().push(callback);     
-    (thunky.run)(thunky);
+  /// Call `run()` with a callback which takes the result itself, shared with
+  /// the cache, so it can keep it without cloning the value.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: run shared keeps the result
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::mpsc;
+  /// use thunky::*;
+  ///
+  /// let thunk = Thunky::new(|resolver: Resolver<Vec<u8>, &'static str>| {
+  ///   resolver.resolve(Ok(vec![1; 1024]))
+  /// });
+  ///
+  /// let (tx, rx) = mpsc::channel();
+  /// for _ in 0..2 {
+  ///   let tx = tx.clone();
+  ///   thunk.run_shared(move |result| tx.send(result).unwrap());
+  /// }
+  ///
+  /// let first = rx.recv().unwrap();
+  /// let second = rx.recv().unwrap();
+  /// assert_eq!(1024, first.as_result().unwrap().len());
+  /// assert!(Shared::ptr_eq(&first, &second));
+  /// ```
+  pub fn run_shared<F>(&self, callback: F)
+  where
+    F: FnOnce(Shared<T, E>) + Send + 'static
+  {
+    if let Some(result) = self.ready() {
+      return callback(Shared::new(result));
+    }
+    self.enqueue(Waiter::shared(Box::new(callback)), None);
+  }
 
-    match thunky.cache.lock().unwrap().as_ref() {
-      Some(cache) => {
-        if cache.is_ok() {
-          *thunky.state.lock().unwrap() = Some(Box::new(Finish {}));
-        } else if cache.is_err() {       
-          *thunky.state.lock().unwrap() = Some(Box::new(Run {}));          
+  /// Call `run()`, but give up waiting after `timeout`.
+  ///
+  /// If the run isn't resolved by then, the callback is called with
+  /// `Error::Timeout`, and the run goes on for the other callbacks. The
+  /// timeout is waited for on the timer set with `ThunkyBuilder::timer()`,
+  /// by default a single thread shared by every thunky, see `ThreadTimer`.
+  /// The timeout stays scheduled even if the run resolves right away, but no
+  /// thread is spawned for it.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: waiter times out
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let clock = Arc::new(ManualClock::new());
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let thunk = ThunkyBuilder::new()
+  ///   .timer(clock.clone())
+  ///   .build(move |resolver: Resolver<u32, &'static str>| {
+  ///     resolvers_clone.lock().unwrap().push(resolver);
+  ///   });
+  ///
+  /// thunk.run_with_timeout(
+  ///   Duration::from_secs(1),
+  ///   |arg: &Result<u32, &'static str>| {
+  ///     assert_eq!(Err("thunky timed out"), *arg);
+  ///   }
+  /// );
+  /// thunk.run(|arg: &Result<u32, &'static str>| {
+  ///   assert_eq!(Ok(1), *arg);
+  /// });
+  ///
+  /// clock.advance(Duration::from_secs(1));
+  /// resolvers.lock().unwrap().pop().unwrap().resolve(Ok(1));
+  /// ```
+  pub fn run_with_timeout<F>(&self, timeout: Duration, callback: F)
+  where
+    F: FnOnce(&Result<T, E>) + Send + 'static
+  {
+    self.enqueue(Waiter::new(Box::new(callback), 0), Some(timeout));
+  }
+
+  /// Call `run()` with `waiter`, with a timeout for its callback. Returns the
+  /// waiter id, if it was queued.
+  pub(crate) fn enqueue(
+    &self,
+    mut waiter: Waiter<T, E>,
+    timeout: Option<Duration>
+  ) -> Option<u64> {
+    if let Some(result) = self.ready() {
+      waiter.callback.call(&result);
+      return None;
+    }
+
+    // While a run is in progress, the waiter is queued without the mutex.
+    let id = self.inbox.id();
+    waiter.id = id;
+    let waiter = match self.inbox.push(waiter) {
+      Ok(()) => {
+        if let Some(timeout) = timeout {
+          (self.defer)(self, timeout, Alarm::Waiter(id));
         }
+        return Some(id);
       },
-      None => {
-        *thunky.state.lock().unwrap() = Some(Box::new(Wait {}));
-      }      
+      Err(waiter) => waiter
+    };
+
+    let (step, generation, changed) = {
+      let mut inner = self.lock();
+      let state = inner.state.take().unwrap();
+      let step = state.run(self, &mut inner, waiter);
+      (step, inner.generation, inner.breaker.changed(self))
+    };
+
+    if let Some((listener, circuit)) = changed {
+      listener(circuit);
+    }
+
+    let waiter = match step {
+      Step::Init | Step::Queued => Some(id),
+      _ => None
+    };
+    if let (Some(timeout), Some(waiter)) = (timeout, waiter) {
+      (self.defer)(self, timeout, Alarm::Waiter(waiter));
+    }
+
+    match step {
+      Step::Init => self.init(generation),
+      Step::Queued => {},
+      Step::Deliver(result, callback) => callback.call(&result),
+      Step::Revalidate(result, callback) => {
+        callback.call(&result);
+        self.init(generation)
+      }
     }
+    waiter
+  }
+
+  /// Return a future which resolves with the result of the thunky.
+  ///
+  /// It goes through the same states as `thunky.run()`: the first poll
+  /// registers the future as a callback, triggering the run function if
+  /// nothing is cached, and it resolves once the run is resolved.
+  /// The output is a clone of the cached result.
+  ///
+  /// Dropping the future before it resolves takes its callback off the
+  /// queue, see `ThunkyBuilder::cancellation()`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: get resolves async
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use std::thread;
+  /// use std::time::Duration;
+  /// use futures::executor::block_on;
+  /// use thunky::*;
+  ///
+  /// let run = move |resolver: Resolver<u32, &'static str>| {
+  ///   thread::spawn(move || {
+  ///     thread::sleep(Duration::from_millis(100));
+  ///     resolver.resolve(Ok(1));
+  ///   });
+  /// };
+  ///
+  /// let thunk = Thunky::new(run);
+  ///
+  /// assert_eq!(Ok(1), block_on(thunk.get()));
+  /// ```
+  pub fn get(&self) -> Get<'_, T, E> {
+    Get::new(self, None)
+  }
+
+  /// Return a future like `thunky.get()`, which resolves with
+  /// `Error::Timeout` if the thunky isn't resolved within `timeout`, see
+  /// `thunky.run_with_timeout()`.
+  pub fn get_with_timeout(&self, timeout: Duration) -> Get<'_, T, E> {
+    Get::new(self, Some(timeout))
+  }
+
+  /// Return a future like `thunky.get()`, which resolves with the result
+  /// shared with the cache instead of a clone of it.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: get shared resolves without cloning
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use futures::executor::block_on;
+  /// use thunky::*;
+  ///
+  /// // Not `Clone`.
+  /// struct Config {
+  ///   name: String
+  /// }
+  ///
+  /// let thunk = Thunky::new(|resolver: Resolver<Config, &'static str>| {
+  ///   resolver.resolve(Ok(Config { name: "prod".to_string() }))
+  /// });
+  ///
+  /// let config = block_on(thunk.get_shared());
+  /// assert_eq!("prod", config.as_result().unwrap().name);
+  /// ```
+  pub fn get_shared(&self) -> GetShared<'_, T, E> {
+    GetShared::new(self, None)
   }
-}
 
-struct Wait {}
+  /// Return the result, shared, if `run()` would deliver it right away.
+  ///
+  /// It never calls the run function nor waits for a run: a thunky which
+  /// isn't resolved, or whose value expired, returns `None`.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: try get is none until resolved
+  ///
+  /// extern crate thunky;
+  ///
+  /// use thunky::*;
+  ///
+  /// let thunk = Thunky::new(|resolver: Resolver<u32, &'static str>| {
+  ///   resolver.resolve(Ok(1))
+  /// });
+  ///
+  /// assert!(thunk.try_get().is_none());
+  ///
+  /// thunk.run(|_: &Result<u32, &str>| {});
+  /// let result = thunk.try_get().unwrap();
+  /// assert_eq!(Ok(&1), result.as_result());
+  /// ```
+  pub fn try_get(&self) -> Option<Shared<T, E>> {
+    self.peek().map(Shared::new)
+  }
 
-impl<T, E> State<T, E> for Wait {
-  fn run (&self, thunky: &Thunky<T, E>, callback: Cb<T, E>) -> () {   
-    thunky.stack.lock().unwrap().push(callback);
-    *thunky.state.lock().unwrap() = Some(Box::new(Wait {}));
+  /// Return a clone of the result, if `run()` would deliver it right away,
+  /// see `thunky.try_get()`.
+  pub fn get_cloned(&self) -> Option<Result<T, E>>
+  where
+    T: Clone,
+    E: Clone
+  {
+    self.try_get().map(|result| result.cloned())
   }
 }
 
-struct Finish {}
+impl<T, E> fmt::Debug for Thunky<T, E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let inner = self.lock();
+    let status = inner.state.as_ref().unwrap().status(self, &inner);
+    let waiters = inner.queue.len();
+    let (attempts, generation) = (inner.attempts, inner.generation);
+    let (resolved_at, failed_at) = (inner.resolved_at, inner.failed_at);
+    drop(inner);
 
-impl<T, E> State<T, E> for Finish {
-  fn run (&self, thunky: &Thunky<T, E>, callback: Cb<T, E>) -> () { 
-    while thunky.stack.lock().unwrap().len() > 0 {
-      let cb = thunky.stack.lock().unwrap().pop().unwrap();
-      cb(thunky.cache.lock().unwrap().as_ref().unwrap());
-    }
-    callback(thunky.cache.lock().unwrap().as_ref().unwrap());
-    *thunky.state.lock().unwrap() = Some(Box::new(Finish {}));
+    f.debug_struct("Thunky")
+      .field("status", &status)
+      .field("waiters", &waiters)
+      .field("attempts", &attempts)
+      .field("generation", &generation)
+      .field("resolved_at", &resolved_at)
+      .field("failed_at", &failed_at)
+      .finish()
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,493 @@
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ptr;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Instant;
+
+use crate::builder::{MapOptions, Options};
+use crate::{CachePolicy, Error, MapRun, MapRunCb, Resolver, SettledCb};
+use crate::Thunky;
+use crate::{ThunkyBuilder, Weigher};
+
+/// A thunky per key, created on first use.
+///
+/// The run function gets the key along with the resolver. Each key goes
+/// through the same states as a single thunky, so concurrent `run()` calls for
+/// a key share one call to the run function, while other keys are resolved
+/// independently.
+///
+/// # Examples
+///
+/// ```
+/// // test: map runs once per key
+///
+/// extern crate thunky;
+///
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use thunky::*;
+///
+/// let calls = AtomicUsize::new(0);
+///
+/// let run = move |key: &String, resolver: Resolver<usize, &str>| {
+///   calls.fetch_add(1, Ordering::SeqCst);
+///   resolver.resolve(Ok(key.len()));
+/// };
+///
+/// let map = ThunkyMap::new(run);
+///
+/// map.run("four".to_string(), |arg: &Result<usize, &str>| {
+///   assert_eq!(4, arg.unwrap());
+/// });
+///
+/// map.run("four".to_string(), |arg: &Result<usize, &str>| {
+///   assert_eq!(4, arg.unwrap());
+/// });
+///
+/// map.run("three".to_string(), |arg: &Result<usize, &str>| {
+///   assert_eq!(5, arg.unwrap());
+/// });
+///
+/// assert_eq!(2, map.len());
+/// ```
+pub struct ThunkyMap<K, T, E> {
+  run: Arc<MapRun<K, T, E>>,
+  options: Options,
+  map: MapOptions,
+  policy: Arc<dyn CachePolicy<T, E>>,
+  weigher: Option<Arc<dyn Weigher<T>>>,
+  entries: Arc<Mutex<Entries<K, T, E>>>,
+  error: fn(Error) -> E
+}
+
+/// Which key a thunky map at its capacity evicts, see
+/// `ThunkyBuilder::capacity()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+  /// Evict the least recently used key.
+  #[default]
+  Lru,
+  /// Evict the least frequently used key, or the least recently used one of
+  /// those.
+  Lfu
+}
+
+/// Counters of a thunky map, see `ThunkyMap::stats()`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MapStats {
+  /// Number of keys in the map.
+  pub len: usize,
+  /// Total weight of the cached values, as last measured.
+  pub weight: u64,
+  /// Number of uses of a key already in the map.
+  pub hits: u64,
+  /// Number of uses of a key which had to be added to the map.
+  pub misses: u64,
+  /// Number of keys evicted.
+  pub evictions: u64,
+  /// Total weight of the evicted keys.
+  pub evicted_weight: u64
+}
+
+/// The keys of the map, guarded by its mutex.
+struct Entries<K, T, E> {
+  map: HashMap<K, Entry<T, E>>,
+  /// The keys by `Entry::rank()`, the first one is evicted first.
+  order: BTreeMap<(u64, u64), K>,
+  /// The keys whose weighed result expires, by when it expires and then by
+  /// `Entry::id`.
+  expiring: BTreeMap<(Instant, u64), K>,
+  eviction: Eviction,
+  /// Incremented on every use of a key.
+  tick: u64,
+  stats: MapStats
+}
+
+struct Entry<T, E> {
+  thunky: Arc<Thunky<T, E>>,
+  /// The `tick` the key was added at, which tells it apart in `expiring`.
+  id: u64,
+  /// The `tick` of the last use.
+  used: u64,
+  /// How many times the key was used.
+  uses: u64,
+  /// The cached result `weight` was measured for.
+  weighed: Weak<Result<T, E>>,
+  weight: u64,
+  /// When the result `weight` was measured for expires.
+  expires: Option<Instant>
+}
+
+impl<T, E> Entry<T, E> {
+  /// Return where the key goes in `Entries::order`.
+  fn rank(&self, eviction: Eviction) -> (u64, u64) {
+    match eviction {
+      Eviction::Lru => (0, self.used),
+      Eviction::Lfu => (self.uses, self.used)
+    }
+  }
+
+  /// Measure the cached result again if it changed, and return the weight
+  /// before and after.
+  fn weigh(&mut self, weigher: &dyn Weigher<T>) -> (u64, u64) {
+    let before = self.weight;
+    match self.thunky.peek() {
+      Some(result) => {
+        if Weak::as_ptr(&self.weighed) != Arc::as_ptr(&result) {
+          self.weighed = Arc::downgrade(&result);
+          self.weight = match &*result {
+            Ok(value) => weigher.weigh(value),
+            Err(_) => 0
+          };
+          self.expires = self.thunky.expires();
+        }
+      },
+      None => {
+        self.weighed = Weak::new();
+        self.weight = 0;
+        self.expires = None;
+      }
+    }
+    (before, self.weight)
+  }
+}
+
+impl<K, T, E> Entries<K, T, E>
+where
+  K: Eq + Hash + Clone
+{
+  /// Add `entry` for `key`, which is not in the map.
+  fn insert(&mut self, key: K, entry: Entry<T, E>) {
+    self.order.insert(entry.rank(self.eviction), key.clone());
+    self.map.insert(key, entry);
+  }
+
+  /// Remove the entry of `key`, and return it.
+  fn remove<Q>(&mut self, key: &Q) -> Option<Entry<T, E>>
+  where
+    K: Borrow<Q>,
+    Q: Eq + Hash + ?Sized
+  {
+    let entry = self.map.remove(key)?;
+    self.order.remove(&entry.rank(self.eviction));
+    if let Some(expires) = entry.expires {
+      self.expiring.remove(&(expires, entry.id));
+    }
+    self.stats.weight -= entry.weight;
+    Some(entry)
+  }
+
+  /// Count a use of `key`, and return its thunky.
+  fn touch(&mut self, key: &K) -> Option<Arc<Thunky<T, E>>> {
+    let eviction = self.eviction;
+    let tick = self.tick;
+    let entry = self.map.get_mut(key)?;
+    let rank = entry.rank(eviction);
+    entry.used = tick;
+    entry.uses += 1;
+    let thunky = Arc::clone(&entry.thunky);
+    let key = self.order.remove(&rank).unwrap();
+    self.order.insert(entry.rank(eviction), key);
+    Some(thunky)
+  }
+
+  /// Measure the cached result of `key` again, if it changed.
+  fn weigh(&mut self, key: &K, weigher: &dyn Weigher<T>) {
+    if let Some(entry) = self.map.get_mut(key) {
+      let expires = entry.expires;
+      let (before, after) = entry.weigh(weigher);
+      self.stats.weight = self.stats.weight - before + after;
+      if entry.expires != expires {
+        if let Some(expires) = expires {
+          self.expiring.remove(&(expires, entry.id));
+        }
+        if let Some(expires) = entry.expires {
+          self.expiring.insert((expires, entry.id), key.clone());
+        }
+      }
+    }
+  }
+
+  /// Measure the cached results which expired by `now` again.
+  fn expire(&mut self, now: Instant, weigher: &dyn Weigher<T>) {
+    while let Some(entry) = self.expiring.first_entry() {
+      if entry.key().0 > now {
+        return;
+      }
+      let key = entry.remove();
+      // A result still served once expired keeps its weight, and is not
+      // looked at again before it changes.
+      self.weigh(&key, weigher);
+    }
+  }
+
+  /// Weigh the result `thunky` was just settled with, if it's still the
+  /// thunky of `key`, and evict other keys if it's over the weight budget.
+  fn settled(
+    &mut self,
+    key: &K,
+    thunky: &Thunky<T, E>,
+    weigher: &dyn Weigher<T>,
+    options: &MapOptions
+  ) {
+    let current = self
+      .map
+      .get(key)
+      .is_some_and(|entry| ptr::eq(Arc::as_ptr(&entry.thunky), thunky));
+    if current {
+      self.weigh(key, weigher);
+      self.trim(options, Some(key), 0);
+    }
+  }
+
+  /// Evict keys until there's room for `room` more keys, within the capacity
+  /// and the weight budget. `keep` is never evicted.
+  fn trim(&mut self, options: &MapOptions, keep: Option<&K>, room: usize) {
+    loop {
+      let over_capacity = options
+        .capacity
+        .is_some_and(|capacity| self.map.len() + room > capacity);
+      let over_weight = options
+        .max_weight
+        .is_some_and(|max_weight| self.stats.weight > max_weight);
+      if !over_capacity && !over_weight {
+        return;
+      }
+
+      let victim = match self.victim(keep) {
+        Some(victim) => victim,
+        None => return
+      };
+      let entry = self.remove(&victim).unwrap();
+      self.stats.evictions += 1;
+      self.stats.evicted_weight += entry.weight;
+    }
+  }
+
+  /// Return the key to evict, among keys whose run is not in progress. Only
+  /// the keys ranked before it are looked at, without locking their thunky.
+  fn victim(&self, keep: Option<&K>) -> Option<K> {
+    self
+      .order
+      .values()
+      .find(|key| Some(*key) != keep && !self.map[*key].thunky.is_running())
+      .cloned()
+  }
+}
+
+impl<K, T, E> ThunkyMap<K, T, E> {
+  /// Create a thunky map with a run function, which takes the key and a
+  /// `Resolver` as parameters, see `Thunky::new()`.
+  pub fn new<F>(run: F) -> ThunkyMap<K, T, E>
+  where
+    F: Fn(&K, Resolver<T, E>) + Send + Sync + 'static,
+    E: From<Error>
+  {
+    ThunkyBuilder::new().build_map(run)
+  }
+
+  pub(crate) fn with_run(
+    run: MapRunCb<K, T, E>,
+    options: Options,
+    map: MapOptions,
+    policy: Arc<dyn CachePolicy<T, E>>,
+    weigher: Option<Arc<dyn Weigher<T>>>
+  ) -> ThunkyMap<K, T, E>
+  where
+    E: From<Error>
+  {
+    let eviction = map.eviction;
+    ThunkyMap {
+      run: Arc::from(run),
+      options,
+      map,
+      policy,
+      weigher,
+      entries: Arc::new(Mutex::new(Entries {
+        map: HashMap::new(),
+        order: BTreeMap::new(),
+        expiring: BTreeMap::new(),
+        eviction,
+        tick: 0,
+        stats: MapStats::default()
+      })),
+      error: E::from
+    }
+  }
+
+  /// Return the number of keys in the map, resolved or not.
+  pub fn len(&self) -> usize {
+    self.entries.lock().unwrap().map.len()
+  }
+
+  /// Return `true` if the map has no keys.
+  pub fn is_empty(&self) -> bool {
+    self.entries.lock().unwrap().map.is_empty()
+  }
+
+  /// Return the counters of the map, to tune its capacity and weight budget.
+  pub fn stats(&self) -> MapStats {
+    let entries = self.entries.lock().unwrap();
+    MapStats {
+      len: entries.map.len(),
+      ..entries.stats
+    }
+  }
+}
+
+impl<K, T, E> ThunkyMap<K, T, E>
+where
+  K: Eq + Hash + Clone + Send + Sync + 'static,
+  T: Send + Sync + 'static,
+  E: Send + Sync + 'static
+{
+  /// Return the thunky of `key`, creating it if needed.
+  ///
+  /// The thunky stays in the map until it's removed or evicted, so this can be
+  /// used to call `thunky.get()` or `thunky.reset()` for a key.
+  pub fn entry(&self, key: K) -> Arc<Thunky<T, E>> {
+    let mut entries = self.entries.lock().unwrap();
+    entries.tick += 1;
+    let tick = entries.tick;
+    if let Some(thunky) = entries.touch(&key) {
+      entries.stats.hits += 1;
+      if let Some(weigher) = &self.weigher {
+        entries.weigh(&key, weigher.as_ref());
+        entries.trim(&self.map, Some(&key), 0);
+      }
+      return thunky;
+    }
+
+    entries.stats.misses += 1;
+    if let Some(weigher) = &self.weigher {
+      entries.expire(self.options.clock.now(), weigher.as_ref());
+    }
+    entries.trim(&self.map, None, 1);
+
+    let run = Arc::clone(&self.run);
+    let options = self.options.clone();
+    let key_clone = key.clone();
+    let settled = self.settled(&key);
+    let thunky = Arc::new_cyclic(|this: &Weak<Thunky<T, E>>| {
+      let mut thunky = Thunky::with_run(
+        Box::new(move |resolver: Resolver<T, E>| run(&key_clone, resolver)),
+        options,
+        Arc::clone(&self.policy),
+        self.error,
+        this
+      );
+      thunky.settled = settled;
+      thunky
+    });
+    entries.insert(key, Entry {
+      thunky: Arc::clone(&thunky),
+      id: tick,
+      used: tick,
+      uses: 1,
+      weighed: Weak::new(),
+      weight: 0,
+      expires: None
+    });
+    thunky
+  }
+
+  /// Return the callback weighing the results of the thunky of `key` as soon
+  /// as its runs are settled, so the weight budget holds without waiting for
+  /// the next use of the map.
+  fn settled(&self, key: &K) -> Option<SettledCb<T, E>> {
+    let weigher = Arc::clone(self.weigher.as_ref()?);
+    let entries = Arc::downgrade(&self.entries);
+    let options = self.map.clone();
+    let key = key.clone();
+    Some(Box::new(move |thunky: &Thunky<T, E>| {
+      if let Some(entries) = entries.upgrade() {
+        let mut entries = entries.lock().unwrap();
+        entries.settled(&key, thunky, weigher.as_ref(), &options);
+      }
+    }))
+  }
+
+  /// Call `run()` of the thunky of `key`, see `Thunky::run()`.
+  ///
+  /// The map is not locked while the run function or the callback is called,
+  /// so they may use the map too.
+  pub fn run<F>(&self, key: K, callback: F)
+  where
+    F: FnOnce(&Result<T, E>) + Send + 'static
+  {
+    self.entry(key).run(callback)
+  }
+
+  /// Return `true` if the map has a thunky for `key`.
+  pub fn contains_key<Q>(&self, key: &Q) -> bool
+  where
+    K: Borrow<Q>,
+    Q: Eq + Hash + ?Sized
+  {
+    self.entries.lock().unwrap().map.contains_key(key)
+  }
+
+  /// Remove the thunky of `key` from the map, and return it.
+  ///
+  /// The next `run()` for `key` creates a new thunky, calling the run function
+  /// again. A run in progress still resolves the returned thunky, as long as
+  /// it's kept alive: once it's dropped, its resolver does nothing.
+  pub fn remove<Q>(&self, key: &Q) -> Option<Arc<Thunky<T, E>>>
+  where
+    K: Borrow<Q>,
+    Q: Eq + Hash + ?Sized
+  {
+    let entry = self.entries.lock().unwrap().remove(key)?;
+    Some(entry.thunky)
+  }
+
+  /// Return the keys with a cached result, along with the result.
+  ///
+  /// Keys still waiting for their run function, or whose value expired, are
+  /// left out.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: resolved entries of map
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use thunky::*;
+  ///
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let map = ThunkyMap::new(move |key: &u32, resolver: Resolver<u32, &str>| {
+  ///   if *key == 1 {
+  ///     resolver.resolve(Ok(10));
+  ///   } else {
+  ///     resolvers_clone.lock().unwrap().push(resolver);
+  ///   }
+  /// });
+  ///
+  /// map.run(1, |_arg: &Result<u32, &'static str>| {});
+  /// map.run(2, |_arg: &Result<u32, &'static str>| {});
+  ///
+  /// let resolved = map.resolved();
+  /// assert_eq!(1, resolved.len());
+  /// assert_eq!(1, resolved[0].0);
+  /// assert_eq!(Ok(10), *resolved[0].1);
+  /// ```
+  pub fn resolved(&self) -> Vec<(K, Arc<Result<T, E>>)> {
+    let entries: Vec<(K, Arc<Thunky<T, E>>)> = self
+      .entries
+      .lock()
+      .unwrap()
+      .map
+      .iter()
+      .map(|(key, entry)| (key.clone(), Arc::clone(&entry.thunky)))
+      .collect();
+
+    entries
+      .into_iter()
+      .filter_map(|(key, thunky)| thunky.peek().map(|result| (key, result)))
+      .collect()
+  }
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+/// What to do with the result of a run, see `CachePolicy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+  /// Cache the result, until the ttl of the thunky expires if it has one.
+  Cache,
+  /// Cache the result for the given duration, instead of the ttl.
+  CacheFor(Duration),
+  /// Deliver the result to the waiting callbacks without caching it, so the
+  /// next `thunky.run()` calls the run function again.
+  Skip
+}
+
+/// Decides which results of a run are cached, see
+/// `ThunkyBuilder::policy()`.
+///
+/// It's called with the thunky locked, so it should be cheap and must not use
+/// the thunky.
+pub trait CachePolicy<T, E>: Send + Sync {
+  fn decide(&self, result: &Result<T, E>) -> Decision;
+}
+
+/// Cache `Ok(T)` and skip `Err(E)`, the policy of a thunky unless told
+/// otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultPolicy;
+
+impl<T, E> CachePolicy<T, E> for DefaultPolicy {
+  fn decide(&self, result: &Result<T, E>) -> Decision {
+    match result {
+      Ok(_) => Decision::Cache,
+      Err(_) => Decision::Skip
+    }
+  }
+}
+
+impl<T, E, F> CachePolicy<T, E> for F
+where
+  F: Fn(&Result<T, E>) -> Decision + Send + Sync
+{
+  fn decide(&self, result: &Result<T, E>) -> Decision {
+    self(result)
+  }
+}
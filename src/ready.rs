@@ -0,0 +1,226 @@
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::inbox::Inbox;
+use crate::state::Inner;
+use crate::sync::{fence, AtomicBool, AtomicPtr, AtomicUsize, Mutex};
+use crate::sync::{MutexGuard, Ordering, UnsafeCell};
+
+/// A published value, along with when it expires.
+pub(crate) type Value<T, E> = (Arc<Result<T, E>>, Option<Instant>);
+
+/// A published value, emptied before it's freed so that model checks catch a
+/// reader still looking at it.
+type Node<T, E> = UnsafeCell<Option<Value<T, E>>>;
+
+/// The resolved value of a thunky, readable without taking its mutex.
+///
+/// Readers count themselves, load the published pointer, and clone the value
+/// it points to before they leave, so they're only counted for a few
+/// instructions. Writers hold the mutex of the thunky, so there is one at a
+/// time: it swaps the pointer and retires the value it replaced, which is
+/// freed right away if no reader is counted, or else by the last reader to
+/// leave. Neither ever waits for the other.
+pub(crate) struct Ready<T, E> {
+  value: AtomicPtr<Node<T, E>>,
+  /// The readers between loading the pointer and cloning its value.
+  readers: AtomicUsize,
+  /// Replaced values not freed yet, because readers were counted when they
+  /// were retired.
+  retired: Mutex<Vec<*mut Node<T, E>>>,
+  /// Whether `retired` may have values, for the last reader to free them.
+  pending: AtomicBool,
+  /// Whether a run is in progress, as of the last time the mutex of the
+  /// thunky was unlocked.
+  running: AtomicBool
+}
+
+unsafe impl<T: Send + Sync, E: Send + Sync> Send for Ready<T, E> {}
+unsafe impl<T: Send + Sync, E: Send + Sync> Sync for Ready<T, E> {}
+
+impl<T, E> Ready<T, E> {
+  pub(crate) fn new() -> Ready<T, E> {
+    Ready {
+      value: AtomicPtr::new(ptr::null_mut()),
+      readers: AtomicUsize::new(0),
+      retired: Mutex::new(Vec::new()),
+      pending: AtomicBool::new(false),
+      running: AtomicBool::new(false)
+    }
+  }
+
+  /// Return whether a run is in progress, without the mutex of the thunky.
+  pub(crate) fn is_running(&self) -> bool {
+    self.running.load(Ordering::Acquire)
+  }
+
+  /// Return the published value, if there's one.
+  pub(crate) fn get(&self) -> Option<Value<T, E>> {
+    self.readers.fetch_add(1, Ordering::Relaxed);
+    // Pairs with the fence of `retire()`: either it sees this reader, or this
+    // reader sees the value that replaced the one it retired.
+    fence(Ordering::SeqCst);
+    let node = self.value.load(Ordering::Acquire);
+    let value = if node.is_null() {
+      None
+    } else {
+      // The value is not freed while this reader is counted.
+      Some(unsafe { &*node }.with(|node| {
+        let (result, expires) = unsafe { (*node).as_ref().unwrap() };
+        (Arc::clone(result), *expires)
+      }))
+    };
+
+    if self.readers.fetch_sub(1, Ordering::AcqRel) == 1 {
+      // Pairs with the fence of `retire()`: either it sees no reader left, or
+      // this reader sees the value it retired.
+      fence(Ordering::SeqCst);
+      if self.pending.load(Ordering::Relaxed) {
+        self.collect();
+      }
+    }
+    value
+  }
+
+  /// Publish `value`, unless it's published already. Must be called with the
+  /// mutex of the thunky held.
+  pub(crate) fn publish(
+    &self,
+    value: &Arc<Result<T, E>>,
+    expires: Option<Instant>
+  ) {
+    let published = self.value.load(Ordering::Relaxed);
+    if !published.is_null() {
+      // Only writers change the pointer and free published values, and this
+      // one holds the mutex.
+      let unchanged = unsafe { &*published }.with(|node| {
+        let (published, published_expires) =
+          unsafe { (*node).as_ref().unwrap() };
+        Arc::ptr_eq(published, value) && *published_expires == expires
+      });
+      if unchanged {
+        return;
+      }
+    }
+
+    let node = Box::new(UnsafeCell::new(Some((Arc::clone(value), expires))));
+    self.replace(Box::into_raw(node));
+  }
+
+  /// Stop serving the published value, if there's one. Must be called with
+  /// the mutex of the thunky held.
+  pub(crate) fn retract(&self) {
+    if !self.value.load(Ordering::Relaxed).is_null() {
+      self.replace(ptr::null_mut());
+    }
+  }
+
+  /// Publish `node`, and retire the value it replaces.
+  fn replace(&self, node: *mut Node<T, E>) {
+    let replaced = self.value.swap(node, Ordering::AcqRel);
+    if !replaced.is_null() {
+      self.retire(replaced);
+    }
+  }
+
+  /// Free `node` once no reader can look at it anymore.
+  fn retire(&self, node: *mut Node<T, E>) {
+    let mut retired = self.retired.lock().unwrap();
+    retired.push(node);
+    self.pending.store(true, Ordering::Relaxed);
+    drop(retired);
+    self.collect();
+  }
+
+  /// Free the retired values, if no reader is counted.
+  fn collect(&self) {
+    let freed = {
+      let mut retired = self.retired.lock().unwrap();
+      // A reader of a retired value counted itself before the value was
+      // replaced, so if none is counted now, none is left.
+      fence(Ordering::SeqCst);
+      if self.readers.load(Ordering::Acquire) != 0 {
+        return;
+      }
+      self.pending.store(false, Ordering::Relaxed);
+      std::mem::take(&mut *retired)
+    };
+    // Dropping a value runs user code, which may read the thunky again.
+    for node in freed {
+      free(node);
+    }
+  }
+}
+
+impl<T, E> Drop for Ready<T, E> {
+  fn drop(&mut self) {
+    // Readers borrow the thunky, so there are none left.
+    let published = self.value.load(Ordering::Relaxed);
+    if !published.is_null() {
+      free(published);
+    }
+    for node in self.retired.lock().unwrap().drain(..) {
+      free(node);
+    }
+  }
+}
+
+/// Empty and free `node`, which no reader looks at anymore.
+fn free<T, E>(node: *mut Node<T, E>) {
+  let node = unsafe { Box::from_raw(node) };
+  node.with_mut(|value| unsafe {
+    *value = None;
+  });
+}
+
+/// The mutex of a thunky, locked. Takes the waiters of the inbox into the
+/// queue when it's locked, and publishes or retracts the cached value for the
+/// fast path when it's unlocked, so whatever a state leaves behind is what
+/// readers without the mutex see.
+pub(crate) struct Locked<'a, T, E> {
+  ready: &'a Ready<T, E>,
+  inbox: &'a Inbox<T, E>,
+  inner: MutexGuard<'a, Inner<T, E>>
+}
+
+impl<'a, T, E> Locked<'a, T, E> {
+  pub(crate) fn new(
+    ready: &'a Ready<T, E>,
+    inbox: &'a Inbox<T, E>,
+    mut inner: MutexGuard<'a, Inner<T, E>>
+  ) -> Locked<'a, T, E> {
+    inbox.close(&mut inner.queue);
+    Locked { ready, inbox, inner }
+  }
+}
+
+impl<'a, T, E> Deref for Locked<'a, T, E> {
+  type Target = Inner<T, E>;
+
+  fn deref(&self) -> &Inner<T, E> {
+    &self.inner
+  }
+}
+
+impl<'a, T, E> DerefMut for Locked<'a, T, E> {
+  fn deref_mut(&mut self) -> &mut Inner<T, E> {
+    &mut self.inner
+  }
+}
+
+impl<'a, T, E> Drop for Locked<'a, T, E> {
+  fn drop(&mut self) {
+    let state = self.inner.state.as_ref();
+    if state.is_some_and(|state| state.is_waiting()) {
+      self.inbox.open();
+    }
+    let running = state.is_some_and(|state| state.is_running());
+    self.ready.running.store(running, Ordering::Release);
+    match (state.and_then(|state| state.resolved()), &self.inner.cache) {
+      (Some(expires), Some(cache)) => self.ready.publish(cache, expires),
+      _ => self.ready.retract()
+    }
+  }
+}
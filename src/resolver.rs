@@ -0,0 +1,96 @@
+use std::sync::{Arc, Weak};
+
+use crate::cancel::Token;
+use crate::{Cancelled, Error, Generation, Thunky};
+
+/// Resolves one run of a thunky, passed to its run function.
+///
+/// A resolver can only be used once: `resolve()` consumes it. It can be moved
+/// to another thread or task, and if it's dropped without being resolved, the
+/// callbacks waiting for it get `Error::Dropped`. When the run is superseded by
+/// `thunky.reset()` its result is ignored.
+pub struct Resolver<T, E> {
+  thunky: Option<Weak<Thunky<T, E>>>,
+  generation: Generation,
+  token: Arc<Token>
+}
+
+impl<T, E> Resolver<T, E> {
+  pub(crate) fn new(
+    thunky: Weak<Thunky<T, E>>,
+    generation: Generation,
+    token: Arc<Token>
+  ) -> Resolver<T, E> {
+    Resolver {
+      thunky: Some(thunky),
+      generation,
+      token
+    }
+  }
+
+  /// Return the generation of the run this resolver was created for.
+  pub fn generation(&self) -> Generation {
+    self.generation
+  }
+
+  /// Whether the run was cancelled or superseded, so its result would be
+  /// ignored and the run function may as well stop working on it.
+  ///
+  /// A run is cancelled when its last waiter goes away, with
+  /// `Cancellation::LastWaiter`, and superseded by `thunky.reset()` or when it
+  /// times out.
+  pub fn is_cancelled(&self) -> bool {
+    self.token.is_cancelled()
+  }
+
+  /// Return a future which resolves once `is_cancelled()` is true, for a run
+  /// function to race its work against.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: cancelled resolver
+  ///
+  /// extern crate futures;
+  /// extern crate thunky;
+  ///
+  /// use std::sync::{Arc, Mutex};
+  /// use futures::executor::block_on;
+  /// use thunky::*;
+  ///
+  /// let resolvers = Arc::new(Mutex::new(Vec::new()));
+  ///
+  /// let resolvers_clone = Arc::clone(&resolvers);
+  /// let thunk = ThunkyBuilder::new()
+  ///   .build(move |resolver: Resolver<u32, &'static str>| {
+  ///     resolvers_clone.lock().unwrap().push(resolver);
+  ///   });
+  ///
+  /// thunk.run(|_: &Result<u32, &'static str>| {});
+  /// let resolver = resolvers.lock().unwrap().pop().unwrap();
+  /// assert!(!resolver.is_cancelled());
+  ///
+  /// thunk.reset();
+  /// block_on(resolver.cancelled());
+  /// assert!(resolver.is_cancelled());
+  /// ```
+  pub fn cancelled(&self) -> Cancelled {
+    Cancelled::new(Arc::clone(&self.token))
+  }
+
+  /// Cache `result` like `thunky.cache()`, unless the run was superseded.
+  pub fn resolve(mut self, result: Result<T, E>) {
+    if let Some(thunky) = self.thunky.take().and_then(|t| t.upgrade()) {
+      thunky.cache_for(self.generation, result);
+    }
+  }
+}
+
+impl<T, E> Drop for Resolver<T, E> {
+  fn drop(&mut self) {
+    if let Some(thunky) = self.thunky.take().and_then(|t| t.upgrade()) {
+      let error = (thunky.error)(Error::Dropped);
+      thunky.cache_for(self.generation, Err(error));
+    }
+  }
+}
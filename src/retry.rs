@@ -0,0 +1,147 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Retry a failed run before delivering its error, see
+/// `ThunkyBuilder::retry()`.
+///
+/// The delay before each retry grows exponentially, from `initial` by
+/// `multiplier` up to `max`, and a random fraction of up to `jitter` of it is
+/// taken off so that thunkies failing together don't retry together.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+  max_attempts: u32,
+  initial: Duration,
+  max: Duration,
+  multiplier: f64,
+  jitter: f64,
+  random: fn(u32) -> f64
+}
+
+impl Retry {
+  /// Call the run function at most `max_attempts` times per run, waiting
+  /// 100ms before the first retry and doubling the delay up to 30s, without
+  /// jitter.
+  pub fn new(max_attempts: u32) -> Retry {
+    Retry {
+      max_attempts,
+      initial: Duration::from_millis(100),
+      max: Duration::from_secs(30),
+      multiplier: 2.0,
+      jitter: 0.0,
+      random
+    }
+  }
+
+  /// Wait `initial` before the first retry, and at most `max` before any.
+  pub fn backoff(mut self, initial: Duration, max: Duration) -> Retry {
+    self.initial = initial;
+    self.max = max;
+    self
+  }
+
+  /// Multiply the delay by `multiplier` after each retry. A negative or NaN
+  /// multiplier is taken as 0, retrying right away after the first delay.
+  pub fn multiplier(mut self, multiplier: f64) -> Retry {
+    self.multiplier = bound(multiplier, f64::INFINITY);
+    self
+  }
+
+  /// Take a random fraction of up to `jitter`, between 0 and 1, off each
+  /// delay. A NaN jitter is taken as 0.
+  pub fn jitter(mut self, jitter: f64) -> Retry {
+    self.jitter = bound(jitter, 1.0);
+    self
+  }
+
+  /// Draw the random fraction of the jitter of the `retry`th retry from
+  /// `random`, instead of from a random source, to get the same delays every
+  /// time. It's taken between 0 and 1, and NaN as 0.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: retry jitter from a fixed source
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let retry = Retry::new(5)
+  ///   .backoff(Duration::from_secs(1), Duration::from_secs(5))
+  ///   .jitter(0.5)
+  ///   .random(|_| 0.5);
+  ///
+  /// assert_eq!(Duration::from_millis(750), retry.delay(1));
+  /// assert_eq!(Duration::from_millis(1500), retry.delay(2));
+  /// ```
+  pub fn random(mut self, random: fn(u32) -> f64) -> Retry {
+    self.random = random;
+    self
+  }
+
+  /// Return how many times the run function is called at most per run.
+  pub fn max_attempts(&self) -> u32 {
+    self.max_attempts
+  }
+
+  /// Return the delay before the `retry`th retry, counting from 1.
+  ///
+  /// # Examples
+  ///
+  /// ```
+  /// // test: retry delays grow exponentially
+  ///
+  /// extern crate thunky;
+  ///
+  /// use std::time::Duration;
+  /// use thunky::*;
+  ///
+  /// let retry = Retry::new(5)
+  ///   .backoff(Duration::from_secs(1), Duration::from_secs(5));
+  ///
+  /// assert_eq!(Duration::from_secs(1), retry.delay(1));
+  /// assert_eq!(Duration::from_secs(2), retry.delay(2));
+  /// assert_eq!(Duration::from_secs(4), retry.delay(3));
+  /// assert_eq!(Duration::from_secs(5), retry.delay(4));
+  /// ```
+  pub fn delay(&self, retry: u32) -> Duration {
+    if self.initial.is_zero() {
+      return Duration::ZERO;
+    }
+    let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+    let delay = self.initial.as_secs_f64() * self.multiplier.powi(exponent);
+    let delay = delay.min(self.max.as_secs_f64());
+    let random = bound((self.random)(retry), 1.0);
+    let delay = delay * (1.0 - self.jitter * random);
+    // A `max` too long for a float, like `Duration::MAX`, rounds up past it.
+    Duration::try_from_secs_f64(delay).unwrap_or(self.max)
+  }
+}
+
+impl PartialEq for Retry {
+  fn eq(&self, other: &Retry) -> bool {
+    self.max_attempts == other.max_attempts
+      && self.initial == other.initial
+      && self.max == other.max
+      && self.multiplier == other.multiplier
+      && self.jitter == other.jitter
+      // The same function may have several addresses, so this only tells
+      // whether both were set from the same one.
+      && self.random as usize == other.random as usize
+  }
+}
+
+/// Return `value` between 0 and `max`, or 0 if it's NaN.
+fn bound(value: f64, max: f64) -> f64 {
+  // Unlike `clamp()`, `max()` and `min()` ignore NaN.
+  value.max(0.0).min(max)
+}
+
+/// Return a random number in `[0, 1)`.
+fn random(seed: u32) -> f64 {
+  let mut hasher = RandomState::new().build_hasher();
+  hasher.write_u32(seed);
+  (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
@@ -0,0 +1,17 @@
+use std::time::Duration;
+
+use super::{BoxFuture, Runtime};
+
+/// async-std runtime, enabled by the `rt-async-std` feature.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStd;
+
+impl Runtime for AsyncStd {
+  fn spawn(&self, future: BoxFuture<()>) {
+    ::async_std::task::spawn(future);
+  }
+
+  fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+    Box::pin(::async_std::task::sleep(duration))
+  }
+}
@@ -0,0 +1,36 @@
+//! Executor integrations.
+//!
+//! The core of thunky does not depend on any executor. A [`Runtime`] is what
+//! `Thunky::from_async_on()` uses to spawn the initializer, and it provides a
+//! timer to go with it. Implementations for tokio, async-std and smol are
+//! available behind the `rt-tokio`, `rt-async-std` and `rt-smol` features.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+#[cfg(feature = "rt-async-std")]
+mod async_std;
+#[cfg(feature = "rt-smol")]
+mod smol;
+#[cfg(feature = "rt-tokio")]
+mod tokio;
+
+#[cfg(feature = "rt-async-std")]
+pub use self::async_std::AsyncStd;
+#[cfg(feature = "rt-smol")]
+pub use self::smol::Smol;
+#[cfg(feature = "rt-tokio")]
+pub use self::tokio::Tokio;
+
+/// An owned, boxed future which can be sent across threads.
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Spawns futures and creates timers on an executor.
+pub trait Runtime: Send + Sync + 'static {
+  /// Run `future` to completion in the background.
+  fn spawn(&self, future: BoxFuture<()>);
+
+  /// Return a future which completes after `duration`.
+  fn sleep(&self, duration: Duration) -> BoxFuture<()>;
+}
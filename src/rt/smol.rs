@@ -0,0 +1,21 @@
+use std::time::Duration;
+
+use super::{BoxFuture, Runtime};
+
+/// smol runtime, enabled by the `rt-smol` feature.
+///
+/// Futures are spawned on smol's global executor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Smol;
+
+impl Runtime for Smol {
+  fn spawn(&self, future: BoxFuture<()>) {
+    ::smol::spawn(future).detach();
+  }
+
+  fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+    Box::pin(async move {
+      ::smol::Timer::after(duration).await;
+    })
+  }
+}
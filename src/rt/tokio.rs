@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use ::tokio::runtime::Handle;
+
+use super::{BoxFuture, Runtime};
+
+/// Tokio 1.x runtime, enabled by the `rt-tokio` feature.
+///
+/// Futures are spawned, and sleeps run, on the runtime behind the wrapped
+/// `Handle`, so it can be used from threads outside of the runtime.
+#[derive(Clone, Debug)]
+pub struct Tokio {
+  handle: Handle
+}
+
+impl Tokio {
+  /// Use the runtime the caller is running in.
+  ///
+  /// # Panics
+  ///
+  /// Panics when called outside of a tokio runtime.
+  pub fn current() -> Tokio {
+    Tokio { handle: Handle::current() }
+  }
+}
+
+impl From<Handle> for Tokio {
+  fn from(handle: Handle) -> Tokio {
+    Tokio { handle }
+  }
+}
+
+impl Runtime for Tokio {
+  fn spawn(&self, future: BoxFuture<()>) {
+    self.handle.spawn(future);
+  }
+
+  fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+    // The timer of the runtime is looked up when the sleep is created, which
+    // may be outside of it.
+    let _guard = self.handle.enter();
+    Box::pin(::tokio::time::sleep(duration))
+  }
+}
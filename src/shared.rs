@@ -0,0 +1,62 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// The result a thunky is resolved with, shared with its cache rather than
+/// cloned out of it, see `Thunky::try_get()`.
+///
+/// Cloning it only clones an `Arc`, so it's cheap to keep or hand around
+/// whatever `T` is. It derefs to the `Result`.
+pub struct Shared<T, E> {
+  result: Arc<Result<T, E>>
+}
+
+impl<T, E> Shared<T, E> {
+  pub(crate) fn new(result: Arc<Result<T, E>>) -> Shared<T, E> {
+    Shared { result }
+  }
+
+  /// Borrow the value or the error.
+  pub fn as_result(&self) -> Result<&T, &E> {
+    self.result.as_ref().as_ref()
+  }
+
+  /// Clone the value or the error out.
+  pub fn cloned(&self) -> Result<T, E>
+  where
+    T: Clone,
+    E: Clone
+  {
+    (*self.result).clone()
+  }
+
+  /// Return the `Arc` the result is shared through.
+  pub fn into_arc(self) -> Arc<Result<T, E>> {
+    self.result
+  }
+
+  /// Whether both are the same result, rather than equal ones.
+  pub fn ptr_eq(this: &Shared<T, E>, other: &Shared<T, E>) -> bool {
+    Arc::ptr_eq(&this.result, &other.result)
+  }
+}
+
+impl<T, E> Clone for Shared<T, E> {
+  fn clone(&self) -> Shared<T, E> {
+    Shared::new(Arc::clone(&self.result))
+  }
+}
+
+impl<T, E> Deref for Shared<T, E> {
+  type Target = Result<T, E>;
+
+  fn deref(&self) -> &Result<T, E> {
+    &self.result
+  }
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for Shared<T, E> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Shared").field(&*self.result).finish()
+  }
+}
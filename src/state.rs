@@ -0,0 +1,649 @@
+use std::cmp::Reverse;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cancel::Token;
+use crate::circuit::Breaker;
+use crate::{Cb, Decision, Shared, SharedCb, Thunky};
+
+/// Identifies a run of the run function, see `Thunky::generation()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Generation(u64);
+
+/// What a thunky is doing, see `Thunky::status()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Status {
+  /// No run is in progress and nothing is cached: the next `thunky.run()`
+  /// calls the run function.
+  Idle,
+  /// The run function was called, and its run is not resolved yet.
+  Initializing,
+  /// A failed run waits for its delay to be retried, see
+  /// `ThunkyBuilder::retry()`.
+  BackingOff,
+  /// The result is cached, and delivered right away.
+  Resolved,
+  /// The cached result is past its ttl, the next `thunky.run()` calls the run
+  /// function again.
+  Expired,
+  /// An expired value is served while it's refreshed, see
+  /// `ThunkyBuilder::stale_while_revalidate()`.
+  Refreshing,
+  /// The last run failed and its result wasn't cached, so the next
+  /// `thunky.run()` calls the run function again.
+  Failed
+}
+
+/// Everything guarded by the thunky's mutex.
+pub(crate) struct Inner<T, E> {
+  pub(crate) state: Option<AnyState>,
+  /// Callbacks waiting for the run, in the order they were queued.
+  pub(crate) queue: Vec<Waiter<T, E>>,
+  /// Incremented on every call of the run function.
+  pub(crate) runs: u64,
+  pub(crate) cache: Option<Arc<Result<T, E>>>,
+  pub(crate) generation: Generation,
+  /// Cancelled when the current generation is superseded.
+  pub(crate) token: Arc<Token>,
+  /// How many times the current run was retried.
+  pub(crate) retries: u32,
+  /// How many times the run function was called for the current run, or the
+  /// last one, retries included.
+  pub(crate) attempts: u32,
+  /// When a run last resolved with `Ok(T)`.
+  pub(crate) resolved_at: Option<Instant>,
+  /// When a run last resolved with `Err(E)`.
+  pub(crate) failed_at: Option<Instant>,
+  /// Whether the last run failed, and wasn't superseded since.
+  pub(crate) failing: bool,
+  pub(crate) breaker: Breaker<T, E>
+}
+
+impl<T, E> Inner<T, E> {
+  pub(crate) fn new() -> Inner<T, E> {
+    Inner {
+      state: Some(AnyState::Run(Run {})),
+      queue: Vec::new(),
+      runs: 0,
+      cache: None,
+      generation: Generation(0),
+      token: Token::new(),
+      retries: 0,
+      attempts: 0,
+      resolved_at: None,
+      failed_at: None,
+      failing: false,
+      breaker: Breaker::new()
+    }
+  }
+
+  /// Change the generation, so the resolver of the current run is ignored.
+  /// Returns the token of the superseded run, to cancel once the mutex is
+  /// released.
+  #[must_use]
+  pub(crate) fn supersede(&mut self) -> Arc<Token> {
+    self.generation = Generation(self.generation.0 + 1);
+    std::mem::replace(&mut self.token, Token::new())
+  }
+
+  /// Return the token of the run for `generation`, which is cancelled already
+  /// if it was superseded.
+  pub(crate) fn token(&self, generation: Generation) -> Arc<Token> {
+    if generation == self.generation {
+      return Arc::clone(&self.token);
+    }
+    let token = Token::new();
+    token.cancel();
+    token
+  }
+
+  /// Queue `waiter`.
+  pub(crate) fn push(&mut self, waiter: Waiter<T, E>) {
+    self.queue.push(waiter);
+  }
+
+  /// Take the queued waiters, in the order to call them: by priority, then
+  /// first come first served.
+  pub(crate) fn take_queue(&mut self) -> Vec<Waiter<T, E>> {
+    let mut queue = std::mem::take(&mut self.queue);
+    queue.sort_by_key(|waiter| Reverse(waiter.priority));
+    queue
+  }
+
+  /// Supersede the current run: drop the cached value and move to `Run`, or
+  /// to `Wait` if callbacks are queued. Returns the token to cancel, and the
+  /// generation to call the run function for, if it has to be called for them.
+  pub(crate) fn reset(&mut self) -> (Arc<Token>, Option<Generation>) {
+    let token = self.supersede();
+    self.cache = None;
+    self.retries = 0;
+    self.failing = false;
+    if self.queue.is_empty() {
+      self.state = Some(AnyState::Run(Run {}));
+      (token, None)
+    } else {
+      self.state = Some(AnyState::Wait(Wait {}));
+      (token, Some(self.generation))
+    }
+  }
+}
+
+/// A callback waiting for a run, see `Thunky::run_with_priority()`.
+pub(crate) struct Waiter<T, E> {
+  /// Set before it's queued, see `Thunky::enqueue()`.
+  pub(crate) id: u64,
+  pub(crate) priority: i32,
+  pub(crate) callback: Callback<T, E>
+}
+
+impl<T, E> Waiter<T, E> {
+  pub(crate) fn new(callback: Cb<T, E>, priority: i32) -> Waiter<T, E> {
+    Waiter {
+      id: 0,
+      priority,
+      callback: Callback::Borrowed(callback)
+    }
+  }
+
+  /// A waiter for the result itself, see `Thunky::run_shared()`.
+  pub(crate) fn shared(callback: SharedCb<T, E>) -> Waiter<T, E> {
+    Waiter {
+      id: 0,
+      priority: 0,
+      callback: Callback::Shared(callback)
+    }
+  }
+}
+
+/// The callback of a waiter, which borrows the result or shares it.
+pub(crate) enum Callback<T, E> {
+  Borrowed(Cb<T, E>),
+  Shared(SharedCb<T, E>)
+}
+
+impl<T, E> Callback<T, E> {
+  pub(crate) fn call(self, result: &Arc<Result<T, E>>) {
+    match self {
+      Callback::Borrowed(callback) => callback(result),
+      Callback::Shared(callback) => callback(Shared::new(Arc::clone(result)))
+    }
+  }
+}
+
+/// What `thunky.run()` has to do once the mutex is released.
+pub(crate) enum Step<T, E> {
+  /// Call the run function.
+  Init,
+  /// The callback was queued.
+  Queued,
+  /// Call the callback with the cached result.
+  Deliver(Arc<Result<T, E>>, Callback<T, E>),
+  /// Call the callback with the stale cached result, then the run function.
+  Revalidate(Arc<Result<T, E>>, Callback<T, E>)
+}
+
+/// What `thunky.cache()` has to do once the mutex is released.
+pub(crate) enum Settled<T, E> {
+  /// Call the queued callbacks with the result.
+  Deliver(Arc<Result<T, E>>),
+  /// Call the run function again after the delay, the callbacks keep waiting.
+  Retry(Duration)
+}
+
+/// A state of the thunky. All methods are called with the mutex held, they
+/// must put the next state into `inner.state` and must not call user code.
+pub(crate) trait State<T, E> {
+  /// Drop the cached value, leaving a run in progress alone.
+  fn invalidate(&self, inner: &mut Inner<T, E>);
+
+  fn status(&self, thunky: &Thunky<T, E>, inner: &Inner<T, E>) -> Status;
+
+  /// Return the cached result, if `run()` would deliver it right away.
+  fn peek(
+    &self,
+    _thunky: &Thunky<T, E>,
+    _inner: &Inner<T, E>
+  ) -> Option<Arc<Result<T, E>>> {
+    None
+  }
+
+  fn run(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E>;
+
+  /// Store `result` and return it, shared, for the queued callbacks, or
+  /// tell to retry the run.
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E>;
+}
+
+/// One of the states, stored in place so that changing state doesn't
+/// allocate.
+pub(crate) enum AnyState {
+  Run(Run),
+  Wait(Wait),
+  Backoff(Backoff),
+  Finish(Finish),
+  Refresh(Refresh)
+}
+
+impl AnyState {
+  /// Whether the run function was called and its run is not resolved yet.
+  pub(crate) fn is_running(&self) -> bool {
+    matches!(
+      self,
+      AnyState::Wait(_) | AnyState::Backoff(_) | AnyState::Refresh(_)
+    )
+  }
+
+  /// Whether a failed run waits to be retried.
+  pub(crate) fn is_backing_off(&self) -> bool {
+    matches!(self, AnyState::Backoff(_))
+  }
+
+  /// Whether `run()` only queues the waiter, so it may be queued without the
+  /// mutex, see `Inbox`.
+  pub(crate) fn is_waiting(&self) -> bool {
+    matches!(self, AnyState::Wait(_) | AnyState::Backoff(_))
+  }
+
+  /// Return when the cached value expires, if `run()` delivers it right away
+  /// until then without changing state. That value is served without taking
+  /// the mutex.
+  pub(crate) fn resolved(&self) -> Option<Option<Instant>> {
+    match self {
+      AnyState::Finish(finish) => Some(finish.expires),
+      _ => None
+    }
+  }
+}
+
+/// Call `$call` on the state `$state` holds, as `$each`.
+macro_rules! dispatch {
+  ($state:expr, $each:ident => $call:expr) => {
+    match $state {
+      AnyState::Run($each) => $call,
+      AnyState::Wait($each) => $call,
+      AnyState::Backoff($each) => $call,
+      AnyState::Finish($each) => $call,
+      AnyState::Refresh($each) => $call
+    }
+  };
+}
+
+impl<T, E> State<T, E> for AnyState {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    dispatch!(self, state => State::<T, E>::invalidate(state, inner))
+  }
+
+  fn status(&self, thunky: &Thunky<T, E>, inner: &Inner<T, E>) -> Status {
+    dispatch!(self, state => state.status(thunky, inner))
+  }
+
+  fn peek(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &Inner<T, E>
+  ) -> Option<Arc<Result<T, E>>> {
+    dispatch!(self, state => state.peek(thunky, inner))
+  }
+
+  fn run(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    dispatch!(self, state => state.run(thunky, inner, waiter))
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    dispatch!(self, state => state.cache(thunky, inner, result))
+  }
+}
+
+pub(crate) struct Run {}
+
+impl<T, E> State<T, E> for Run {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    inner.state = Some(AnyState::Run(Run {}));
+  }
+
+  fn status(&self, _thunky: &Thunky<T, E>, inner: &Inner<T, E>) -> Status {
+    if inner.failing {
+      Status::Failed
+    } else {
+      Status::Idle
+    }
+  }
+
+  fn run(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    if let Some(error) = inner.breaker.check(thunky) {
+      inner.state = Some(AnyState::Run(Run {}));
+      return Step::Deliver(error, waiter.callback);
+    }
+
+    inner.push(waiter);
+    inner.state = Some(AnyState::Wait(Wait {}));
+    Step::Init
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    settle(thunky, inner, result, false)
+  }
+}
+
+pub(crate) struct Wait {}
+
+impl<T, E> State<T, E> for Wait {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    inner.state = Some(AnyState::Wait(Wait {}));
+  }
+
+  fn status(&self, _thunky: &Thunky<T, E>, _inner: &Inner<T, E>) -> Status {
+    Status::Initializing
+  }
+
+  fn run(
+    &self,
+    _thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    inner.push(waiter);
+    inner.state = Some(AnyState::Wait(Wait {}));
+    Step::Queued
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    settle(thunky, inner, result, true)
+  }
+}
+
+/// A failed run waits for its delay before the run function is called again,
+/// see `ThunkyBuilder::retry()`.
+pub(crate) struct Backoff {}
+
+impl<T, E> State<T, E> for Backoff {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    inner.state = Some(AnyState::Backoff(Backoff {}));
+  }
+
+  fn status(&self, _thunky: &Thunky<T, E>, _inner: &Inner<T, E>) -> Status {
+    Status::BackingOff
+  }
+
+  fn run(
+    &self,
+    _thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    inner.push(waiter);
+    inner.state = Some(AnyState::Backoff(Backoff {}));
+    Step::Queued
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    settle(thunky, inner, result, true)
+  }
+}
+
+pub(crate) struct Finish {
+  /// When the cached value expires, if the thunky has a ttl.
+  expires: Option<Instant>
+}
+
+impl<T, E> State<T, E> for Finish {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    inner.cache = None;
+    inner.state = Some(AnyState::Run(Run {}));
+  }
+
+  fn status(&self, thunky: &Thunky<T, E>, _inner: &Inner<T, E>) -> Status {
+    if is_expired(thunky, self.expires) {
+      Status::Expired
+    } else {
+      Status::Resolved
+    }
+  }
+
+  fn peek(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &Inner<T, E>
+  ) -> Option<Arc<Result<T, E>>> {
+    if is_expired(thunky, self.expires) && !is_servable(thunky, self.expires) {
+      return None;
+    }
+    inner.cache.clone()
+  }
+
+  fn run(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    let expired = is_expired(thunky, self.expires);
+    if expired && !is_servable(thunky, self.expires) {
+      inner.cache = None;
+      return Run {}.run(thunky, inner, waiter);
+    }
+
+    let cache = Arc::clone(inner.cache.as_ref().unwrap());
+    // While the circuit is open the stale value is served without a refresh.
+    if expired && inner.breaker.check(thunky).is_none() {
+      inner.state = Some(AnyState::Refresh(Refresh { expires: self.expires }));
+      return Step::Revalidate(cache, waiter.callback);
+    }
+
+    inner.state = Some(AnyState::Finish(Finish { expires: self.expires }));
+    Step::Deliver(cache, waiter.callback)
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    let decision = thunky.policy.decide(&result);
+    let keep = !is_expired(thunky, self.expires)
+      || (decision == Decision::Skip && is_servable(thunky, self.expires));
+    if !keep {
+      inner.cache = None;
+      return store(thunky, inner, result, decision, false);
+    }
+
+    inner.state = Some(AnyState::Finish(Finish { expires: self.expires }));
+    Settled::Deliver(Arc::new(result))
+  }
+}
+
+/// An expired value is being refreshed, while still being served.
+pub(crate) struct Refresh {
+  expires: Option<Instant>
+}
+
+impl<T, E> State<T, E> for Refresh {
+  fn invalidate(&self, inner: &mut Inner<T, E>) {
+    inner.cache = None;
+    inner.state = Some(AnyState::Wait(Wait {}));
+  }
+
+  fn status(&self, _thunky: &Thunky<T, E>, _inner: &Inner<T, E>) -> Status {
+    Status::Refreshing
+  }
+
+  fn peek(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &Inner<T, E>
+  ) -> Option<Arc<Result<T, E>>> {
+    if !is_servable(thunky, self.expires) {
+      return None;
+    }
+    inner.cache.clone()
+  }
+
+  fn run(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    waiter: Waiter<T, E>
+  ) -> Step<T, E> {
+    if !is_servable(thunky, self.expires) {
+      inner.cache = None;
+      return Wait {}.run(thunky, inner, waiter);
+    }
+
+    inner.state = Some(AnyState::Refresh(Refresh { expires: self.expires }));
+    Step::Deliver(
+      Arc::clone(inner.cache.as_ref().unwrap()),
+      waiter.callback
+    )
+  }
+
+  fn cache(
+    &self,
+    thunky: &Thunky<T, E>,
+    inner: &mut Inner<T, E>,
+    result: Result<T, E>
+  ) -> Settled<T, E> {
+    let decision = thunky.policy.decide(&result);
+    if decision == Decision::Skip && is_servable(thunky, self.expires) {
+      record(thunky, inner, &result);
+      inner.breaker.record(thunky, result.is_err());
+      inner.state = Some(AnyState::Finish(Finish { expires: self.expires }));
+      return Settled::Deliver(Arc::new(result));
+    }
+
+    inner.cache = None;
+    store(thunky, inner, result, decision, true)
+  }
+}
+
+/// Whether a value expiring at `expires` is past its ttl.
+fn is_expired<T, E>(thunky: &Thunky<T, E>, expires: Option<Instant>) -> bool {
+  match expires {
+    Some(expires) => thunky.options.clock.now() >= expires,
+    None => false
+  }
+}
+
+/// Whether a value expiring at `expires` may be served while it's refreshed.
+fn is_servable<T, E>(thunky: &Thunky<T, E>, expires: Option<Instant>) -> bool {
+  let options = &thunky.options;
+  if !options.stale_while_revalidate {
+    return false;
+  }
+
+  match (expires, options.max_stale) {
+    // A max stale too long to be added to the expiry is never reached.
+    (Some(expires), Some(max_stale)) => expires
+      .checked_add(max_stale)
+      .map_or(true, |stale| options.clock.now() < stale),
+    _ => true
+  }
+}
+
+/// Cache `result` if the policy of the thunky says so, see `store()`.
+fn settle<T, E>(
+  thunky: &Thunky<T, E>,
+  inner: &mut Inner<T, E>,
+  result: Result<T, E>,
+  running: bool
+) -> Settled<T, E> {
+  let decision = thunky.policy.decide(&result);
+  store(thunky, inner, result, decision, running)
+}
+
+/// A cached result finishes the thunky, a skipped one arms `Run` again, or
+/// is retried if it's an `Err(E)`, the thunky has retries left and `result`
+/// is the result of a run in progress rather than one passed to
+/// `thunky.cache()` between runs.
+fn store<T, E>(
+  thunky: &Thunky<T, E>,
+  inner: &mut Inner<T, E>,
+  result: Result<T, E>,
+  decision: Decision,
+  running: bool
+) -> Settled<T, E> {
+  record(thunky, inner, &result);
+  let ttl = match decision {
+    Decision::Cache => thunky.options.ttl,
+    Decision::CacheFor(ttl) => Some(ttl),
+    Decision::Skip => {
+      if let (Err(_), Some(retry)) = (&result, thunky.options.retry) {
+        if running && inner.retries + 1 < retry.max_attempts() {
+          inner.retries += 1;
+          inner.state = Some(AnyState::Backoff(Backoff {}));
+          return Settled::Retry(retry.delay(inner.retries));
+        }
+      }
+      inner.retries = 0;
+      inner.failing = result.is_err();
+      inner.breaker.record(thunky, result.is_err());
+      inner.state = Some(AnyState::Run(Run {}));
+      return Settled::Deliver(Arc::new(result));
+    }
+  };
+
+  let result = Arc::new(result);
+  // A ttl too long to be added to now never expires.
+  let now = thunky.options.clock.now();
+  let expires = ttl.and_then(|ttl| now.checked_add(ttl));
+  inner.retries = 0;
+  inner.failing = false;
+  inner.breaker.record(thunky, false);
+  inner.cache = Some(Arc::clone(&result));
+  inner.state = Some(AnyState::Finish(Finish { expires }));
+  Settled::Deliver(result)
+}
+
+/// Note when a run resolved, for `thunky.resolved_at()` and
+/// `thunky.failed_at()`.
+fn record<T, E>(
+  thunky: &Thunky<T, E>,
+  inner: &mut Inner<T, E>,
+  result: &Result<T, E>
+) {
+  let now = Some(thunky.options.clock.now());
+  match result {
+    Ok(_) => inner.resolved_at = now,
+    Err(_) => inner.failed_at = now
+  }
+}
@@ -0,0 +1,42 @@
+//! The synchronization primitives of the state machine: std's, or loom's when
+//! built with `RUSTFLAGS="--cfg thunky_loom"`, so `tests/loom.rs` can model
+//! check every interleaving of them.
+
+#[cfg(thunky_loom)]
+pub(crate) use loom::cell::UnsafeCell;
+#[cfg(thunky_loom)]
+pub(crate) use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(thunky_loom)]
+pub(crate) use loom::sync::atomic::AtomicU64;
+#[cfg(thunky_loom)]
+pub(crate) use loom::sync::atomic::{fence, Ordering};
+#[cfg(thunky_loom)]
+pub(crate) use loom::sync::{Mutex, MutexGuard};
+
+#[cfg(not(thunky_loom))]
+pub(crate) use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(not(thunky_loom))]
+pub(crate) use std::sync::atomic::AtomicU64;
+#[cfg(not(thunky_loom))]
+pub(crate) use std::sync::atomic::{fence, Ordering};
+#[cfg(not(thunky_loom))]
+pub(crate) use std::sync::{Mutex, MutexGuard};
+
+/// `std::cell::UnsafeCell` with the closure based API of loom's.
+#[cfg(not(thunky_loom))]
+pub(crate) struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(thunky_loom))]
+impl<T> UnsafeCell<T> {
+  pub(crate) fn new(value: T) -> UnsafeCell<T> {
+    UnsafeCell(std::cell::UnsafeCell::new(value))
+  }
+
+  pub(crate) fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+    f(self.0.get())
+  }
+
+  pub(crate) fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+    f(self.0.get())
+  }
+}
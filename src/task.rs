@@ -0,0 +1,87 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::rt::BoxFuture;
+use crate::timer;
+use crate::Resolver;
+
+/// Drives the future of an async initializer and resolves its run with the
+/// output.
+///
+/// The task is its own waker. It's first polled by the caller starting the
+/// run, then a wake-up only schedules a poll on a worker of `ThreadTimer`, so
+/// the waking thread never runs the future. `notified` makes sure a poll is
+/// scheduled once per wake-up arriving after the last poll started.
+pub(crate) struct Task<T, E> {
+  future: Mutex<Option<BoxFuture<Result<T, E>>>>,
+  notified: AtomicBool,
+  resolver: Mutex<Option<Resolver<T, E>>>
+}
+
+impl<T, E> Task<T, E>
+where
+  T: Send + Sync + 'static,
+  E: Send + Sync + 'static
+{
+  pub(crate) fn spawn<F>(future: F, resolver: Resolver<T, E>)
+  where
+    F: Future<Output = Result<T, E>> + Send + 'static
+  {
+    let task = Arc::new(Task {
+      future: Mutex::new(Some(Box::pin(future))),
+      notified: AtomicBool::new(true),
+      resolver: Mutex::new(Some(resolver))
+    });
+    task.poll();
+  }
+
+  /// Schedule a poll, unless one is scheduled already.
+  fn schedule(self: Arc<Self>) {
+    if !self.notified.swap(true, Ordering::SeqCst) {
+      timer::spawn(Box::new(move || self.poll()));
+    }
+  }
+
+  fn poll(self: &Arc<Self>) {
+    let result = {
+      // A poll scheduled while another one runs waits for it, then polls
+      // again for the wake-up it was scheduled for.
+      let mut future = self.future.lock().unwrap();
+      self.notified.store(false, Ordering::SeqCst);
+
+      let waker = Waker::from(Arc::clone(self));
+      let poll = match future.as_mut() {
+        Some(fut) => fut.as_mut().poll(&mut Context::from_waker(&waker)),
+        None => return
+      };
+      match poll {
+        Poll::Pending => return,
+        Poll::Ready(result) => {
+          *future = None;
+          result
+        }
+      }
+    };
+
+    let resolver = self.resolver.lock().unwrap().take();
+    if let Some(resolver) = resolver {
+      resolver.resolve(result);
+    }
+  }
+}
+
+impl<T, E> Wake for Task<T, E>
+where
+  T: Send + Sync + 'static,
+  E: Send + Sync + 'static
+{
+  fn wake(self: Arc<Self>) {
+    self.schedule();
+  }
+
+  fn wake_by_ref(self: &Arc<Self>) {
+    Arc::clone(self).schedule();
+  }
+}
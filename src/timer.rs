@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::rt::Runtime;
+
+/// A callback called by a `Timer`.
+pub type TimerCb = Box<dyn FnOnce() + Send>;
+
+/// Calls callbacks after a delay, used to wait before retrying a failed run,
+/// see `ThunkyBuilder::timer()`.
+pub trait Timer: Send + Sync {
+  /// Call `callback` once `delay` has passed. A delay too long for the clock,
+  /// like `Duration::MAX`, never passes: the callback is dropped instead.
+  ///
+  /// It's called once the thunky is unlocked, but between two steps of a
+  /// run, so it must not panic.
+  fn schedule(&self, delay: Duration, callback: TimerCb);
+}
+
+/// A timer waiting on one thread, shared by every thunky and started on first
+/// use. It's the default timer.
+///
+/// Callbacks which are due are handed to a pool of worker threads, which
+/// grows when every worker is busy and shrinks when they're idle for a while:
+/// a retry calls the run function there, and a run function which blocks only
+/// holds its own worker. Use a `RuntimeTimer` to wait on an executor instead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadTimer;
+
+impl Timer for ThreadTimer {
+  fn schedule(&self, delay: Duration, callback: TimerCb) {
+    static TIMERS: OnceLock<Sender<Deadline>> = OnceLock::new();
+
+    let at = match Instant::now().checked_add(delay) {
+      Some(at) => at,
+      None => return
+    };
+
+    let timers = TIMERS.get_or_init(|| {
+      let (sender, receiver) = mpsc::channel();
+      thread::Builder::new()
+        .name("thunky-timer".to_string())
+        .spawn(move || wait(receiver))
+        .unwrap();
+      sender
+    });
+    let deadline = Deadline { at, callback };
+    // The thread never exits, so the receiver is never dropped.
+    timers.send(deadline).unwrap();
+  }
+}
+
+/// A callback of `ThreadTimer`, and when to call it.
+struct Deadline {
+  at: Instant,
+  callback: TimerCb
+}
+
+/// The loop of the `ThreadTimer` thread: hand the callbacks which are due to
+/// the workers, then wait for the next one, or for a new one to be scheduled.
+fn wait(receiver: Receiver<Deadline>) {
+  // By deadline, then in the order they were scheduled.
+  let mut deadlines: BTreeMap<(Instant, u64), TimerCb> = BTreeMap::new();
+  let mut scheduled = 0;
+
+  loop {
+    let now = Instant::now();
+    while let Some(entry) = deadlines.first_entry() {
+      if entry.key().0 > now {
+        break;
+      }
+      spawn(entry.remove());
+    }
+
+    let received = match deadlines.keys().next() {
+      Some(&(at, _)) => {
+        receiver.recv_timeout(at.saturating_duration_since(Instant::now()))
+      },
+      None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected)
+    };
+    match received {
+      Ok(deadline) => {
+        scheduled += 1;
+        deadlines.insert((deadline.at, scheduled), deadline.callback);
+      },
+      Err(RecvTimeoutError::Timeout) => {},
+      Err(RecvTimeoutError::Disconnected) => return
+    }
+  }
+}
+
+/// How long a worker of `ThreadTimer` waits for a callback before it exits.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Call `callback` on one of the worker threads of `ThreadTimer`, started on
+/// first use.
+pub(crate) fn spawn(callback: TimerCb) {
+  static WORKERS: OnceLock<Workers> = OnceLock::new();
+
+  WORKERS.get_or_init(Workers::new).call(callback);
+}
+
+/// The worker threads of `ThreadTimer`, also polling the futures of
+/// `Thunky::from_async()`.
+struct Workers {
+  /// The number of workers waiting for a callback. Only changed with the
+  /// mutex held, which is also held to send them a callback.
+  idle: Arc<Mutex<usize>>,
+  sender: Sender<TimerCb>,
+  /// Shared by the idle workers, one of them waits on it at a time.
+  receiver: Arc<Mutex<Receiver<TimerCb>>>
+}
+
+impl Workers {
+  fn new() -> Workers {
+    let (sender, receiver) = mpsc::channel();
+    Workers {
+      idle: Arc::new(Mutex::new(0)),
+      sender,
+      receiver: Arc::new(Mutex::new(receiver))
+    }
+  }
+
+  /// Call `callback` on an idle worker, or on a new one if they're all busy.
+  fn call(&self, callback: TimerCb) {
+    let mut idle = self.idle.lock().unwrap();
+    if *idle > 0 {
+      *idle -= 1;
+      // The receiver is only dropped along with the workers.
+      self.sender.send(callback).unwrap();
+      return;
+    }
+    drop(idle);
+
+    let idle = Arc::clone(&self.idle);
+    let receiver = Arc::clone(&self.receiver);
+    // Without a worker the callback is dropped, like a timer that never
+    // fires, rather than stopping every other timer.
+    let _ = thread::Builder::new()
+      .name("thunky-timer-worker".to_string())
+      .spawn(move || work(callback, &idle, &receiver));
+  }
+}
+
+/// The loop of a worker of `ThreadTimer`: call `callback`, then wait for the
+/// next one, until none comes for `IDLE_TIMEOUT`.
+fn work(
+  mut callback: TimerCb,
+  idle: &Mutex<usize>,
+  receiver: &Mutex<Receiver<TimerCb>>
+) {
+  loop {
+    // A callback which panics must not stop the worker.
+    let _ = panic::catch_unwind(AssertUnwindSafe(callback));
+
+    *idle.lock().unwrap() += 1;
+    let received = receiver.lock().unwrap().recv_timeout(IDLE_TIMEOUT);
+    callback = match received {
+      Ok(callback) => callback,
+      Err(_) => {
+        let mut idle = idle.lock().unwrap();
+        // A callback may have been sent since this worker timed out, with the
+        // idle count already taken down for it. If another worker is waiting
+        // on the receiver, that one gets it instead.
+        let sent = match receiver.try_lock() {
+          Ok(receiver) => receiver.try_recv().ok(),
+          Err(_) => None
+        };
+        match sent {
+          Some(callback) => callback,
+          None => {
+            *idle -= 1;
+            return;
+          }
+        }
+      }
+    };
+  }
+}
+
+/// A timer sleeping on the executor of a `Runtime`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RuntimeTimer<R>(pub R);
+
+impl<R> Timer for RuntimeTimer<R>
+where
+  R: Runtime
+{
+  fn schedule(&self, delay: Duration, callback: TimerCb) {
+    let sleep = self.0.sleep(delay);
+    self.0.spawn(Box::pin(async move {
+      sleep.await;
+      callback();
+    }));
+  }
+}
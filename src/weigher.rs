@@ -0,0 +1,17 @@
+/// Measures a cached value, for the weight budget of a thunky map, see
+/// `ThunkyBuilder::max_weight()`.
+///
+/// It's called with the map locked, so it should be cheap and must not use
+/// the map.
+pub trait Weigher<T>: Send + Sync {
+  fn weigh(&self, value: &T) -> u64;
+}
+
+impl<T, F> Weigher<T> for F
+where
+  F: Fn(&T) -> u64 + Send + Sync
+{
+  fn weigh(&self, value: &T) -> u64 {
+    self(value)
+  }
+}
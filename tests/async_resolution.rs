@@ -0,0 +1,188 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::future;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Duration;
+
+use common::{collect, wait_for, Pending, Results};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use thunky::{Resolver, Thunky};
+
+#[test]
+fn ok_resolved_while_waiting_finishes() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert!(results.lock().unwrap().is_empty());
+
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn err_resolved_while_waiting_rearms_run() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("stop"));
+  assert_eq!(vec![Err("stop")], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  assert_eq!(1, results.lock().unwrap().len());
+
+  pending.resolve(Ok(2));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err("stop"), Ok(2), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn ok_is_kept_after_later_cache() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  thunk.cache(Err("ignored"));
+  thunk.cache(Ok(2));
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn cache_while_waiting_finishes() {
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+  thunk.cache(Ok(1));
+  pending.resolve(Ok(2));
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn resolve_from_another_thread() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+
+  let resolver = pending.take();
+  thread::spawn(move || {
+    thread::sleep(Duration::from_millis(50));
+    resolver.resolve(Ok(1));
+  })
+  .join()
+  .unwrap();
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn get_after_async_resolution() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = Thunky::new(Box::new(
+    move |resolver: Resolver<u32, &'static str>| {
+      calls_clone.fetch_add(1, Ordering::SeqCst);
+      thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        resolver.resolve(Ok(1));
+      });
+    }
+  ));
+
+  assert_eq!(Ok(1), block_on(thunk.get()));
+  assert_eq!(Ok(1), block_on(thunk.get()));
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn from_async_resolved_later() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let senders = Arc::new(Mutex::new(Vec::new()));
+
+  let calls_clone = Arc::clone(&calls);
+  let senders_clone = Arc::clone(&senders);
+  let thunk = Thunky::from_async(move || {
+    calls_clone.fetch_add(1, Ordering::SeqCst);
+    let (tx, rx) = oneshot::channel::<Result<u32, &'static str>>();
+    senders_clone.lock().unwrap().push(tx);
+    async move { rx.await.unwrap() }
+  });
+
+  let results = Results::default();
+  thunk.run(collect(&results));
+  senders.lock().unwrap().remove(0).send(Err("stop")).unwrap();
+  wait_for(&results, 1);
+  assert_eq!(vec![Err("stop")], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+  senders.lock().unwrap().remove(0).send(Ok(2)).unwrap();
+
+  assert_eq!(Ok(2), block_on(thunk.get()));
+  assert_eq!(vec![Err("stop"), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn from_async_is_not_polled_by_the_waker() {
+  let polled = Arc::new(Mutex::new(Vec::new()));
+  let waker = Arc::new(Mutex::new(None::<Waker>));
+
+  let polled_clone = Arc::clone(&polled);
+  let waker_clone = Arc::clone(&waker);
+  let thunk = Thunky::from_async(move || {
+    let polled = Arc::clone(&polled_clone);
+    let waker = Arc::clone(&waker_clone);
+    future::poll_fn(move |cx: &mut Context<'_>| {
+      let mut polled = polled.lock().unwrap();
+      polled.push(thread::current().id());
+      if polled.len() == 2 {
+        return Poll::Ready(Ok::<u32, &'static str>(1));
+      }
+      *waker.lock().unwrap() = Some(cx.waker().clone());
+      Poll::Pending
+    })
+  });
+
+  let results = Results::default();
+  thunk.run(collect(&results));
+  let waking = thread::spawn(move || {
+    waker.lock().unwrap().take().unwrap().wake();
+    thread::current().id()
+  })
+  .join()
+  .unwrap();
+
+  wait_for(&results, 1);
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+  let polled = polled.lock().unwrap();
+  assert_eq!(thread::current().id(), polled[0]);
+  assert_ne!(waking, polled[1]);
+}
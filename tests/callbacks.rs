@@ -0,0 +1,78 @@
+mod common;
+
+use std::cell::Cell;
+use std::sync::mpsc;
+
+use common::{OnceCallback, Pending};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use thunky::{Resolver, Thunky, ThunkyMap};
+
+#[test]
+fn fn_once_callback_moves_sender_out() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  let (tx, rx) = oneshot::channel();
+  thunk.run(move |arg: &Result<u32, &'static str>| {
+    tx.send(*arg).unwrap();
+  });
+  pending.resolve(Ok(1));
+  assert_eq!(Ok(Ok(1)), block_on(rx));
+
+  // Resolved: the callback is called right away.
+  let (tx, rx) = oneshot::channel();
+  thunk.run(move |arg: &Result<u32, &'static str>| {
+    tx.send(*arg).unwrap();
+  });
+  assert_eq!(Ok(Ok(1)), block_on(rx));
+}
+
+#[test]
+fn callback_need_not_be_sync() {
+  let thunk = Thunky::new(|resolver: Resolver<u32, &'static str>| {
+    resolver.resolve(Ok(1))
+  });
+
+  let (tx, rx) = mpsc::channel();
+  let seen = Cell::new(0);
+  thunk.run(move |arg: &Result<u32, &'static str>| {
+    seen.set(*arg.as_ref().unwrap());
+    tx.send(seen.get()).unwrap();
+  });
+  assert_eq!(1, rx.recv().unwrap());
+}
+
+#[test]
+fn boxed_callbacks_are_queued_as_is() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  let (tx, rx) = mpsc::channel();
+  for n in 0..3 {
+    let tx = tx.clone();
+    let callback: OnceCallback<u32> =
+      Box::new(move |arg| tx.send((n, *arg)).unwrap());
+    thunk.run_boxed(callback);
+  }
+  pending.resolve(Ok(1));
+  drop(tx);
+
+  let mut delivered: Vec<_> = rx.iter().collect();
+  delivered.sort();
+  assert_eq!(vec![(0, Ok(1)), (1, Ok(1)), (2, Ok(1))], delivered);
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn map_takes_closures() {
+  let map = ThunkyMap::new(|key: &u32, resolver: Resolver<u32, &'static str>| {
+    resolver.resolve(Ok(key * 10))
+  });
+
+  let (tx, rx) = mpsc::channel();
+  map.run(2, move |arg: &Result<u32, &'static str>| {
+    tx.send(*arg).unwrap();
+  });
+  assert_eq!(Ok(20), rx.recv().unwrap());
+}
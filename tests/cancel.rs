@@ -0,0 +1,156 @@
+mod common;
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{collect, Pending, Results};
+use futures::task::noop_waker_ref;
+use thunky::{Cancellation, Get, ManualClock, ThunkyBuilder};
+
+fn poll<T, E>(get: &mut Get<'_, T, E>) -> Poll<Result<T, E>>
+where
+  T: Clone + Send + 'static,
+  E: Clone + Send + 'static
+{
+  Pin::new(get).poll(&mut Context::from_waker(noop_waker_ref()))
+}
+
+#[test]
+fn detached_run_goes_on_without_waiters() {
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  let mut get = thunk.get();
+  assert!(poll(&mut get).is_pending());
+  drop(get);
+
+  let resolver = pending.take();
+  assert!(!resolver.is_cancelled());
+  resolver.resolve(Ok(1));
+
+  let mut get = thunk.get();
+  assert_eq!(Poll::Ready(Ok(1)), poll(&mut get));
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn dropping_last_waiter_cancels_run() {
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .cancellation(Cancellation::LastWaiter)
+    .build(pending.run_fn());
+  let generation = thunk.generation();
+
+  let mut first = thunk.get();
+  let mut second = thunk.get();
+  assert!(poll(&mut first).is_pending());
+  assert!(poll(&mut second).is_pending());
+  let resolver = pending.take();
+
+  drop(first);
+  assert!(!resolver.is_cancelled());
+  drop(second);
+  assert!(resolver.is_cancelled());
+  assert_ne!(generation, thunk.generation());
+
+  // The late result of the cancelled run is ignored, and the next waiter
+  // starts a new run.
+  resolver.resolve(Ok(1));
+  let mut get = thunk.get();
+  assert!(poll(&mut get).is_pending());
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(2));
+  assert_eq!(Poll::Ready(Ok(2)), poll(&mut get));
+}
+
+#[test]
+fn callbacks_keep_run_going() {
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .cancellation(Cancellation::LastWaiter)
+    .build(pending.run_fn());
+
+  let mut get = thunk.get();
+  assert!(poll(&mut get).is_pending());
+  thunk.run(collect(&results));
+  drop(get);
+
+  let resolver = pending.take();
+  assert!(!resolver.is_cancelled());
+  resolver.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn resolved_future_does_not_cancel() {
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .cancellation(Cancellation::LastWaiter)
+    .build(pending.run_fn());
+
+  let mut get = thunk.get();
+  assert!(poll(&mut get).is_pending());
+  pending.resolve(Ok(1));
+  drop(get);
+
+  let mut get = thunk.get();
+  assert_eq!(Poll::Ready(Ok(1)), poll(&mut get));
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn timed_out_last_waiter_cancels_run() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .cancellation(Cancellation::LastWaiter)
+    .build(pending.run_fn());
+
+  thunk.run_with_timeout(Duration::from_secs(1), collect(&results));
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(vec![Err("thunky timed out")], *results.lock().unwrap());
+  assert!(pending.take().is_cancelled());
+}
+
+struct Flag(Arc<AtomicBool>);
+
+impl Drop for Flag {
+  fn drop(&mut self) {
+    self.0.store(true, Ordering::SeqCst);
+  }
+}
+
+#[test]
+fn cancelled_async_init_is_dropped() {
+  let dropped = Arc::new(AtomicBool::new(false));
+  let dropped_clone = Arc::clone(&dropped);
+  let thunk = ThunkyBuilder::new()
+    .cancellation(Cancellation::LastWaiter)
+    .build_async(move || {
+      let flag = Flag(Arc::clone(&dropped_clone));
+      async move {
+        futures::future::pending::<()>().await;
+        drop(flag);
+        Ok::<u32, thunky::Error>(1)
+      }
+    });
+
+  let mut get = thunk.get();
+  assert!(poll(&mut get).is_pending());
+  assert!(!dropped.load(Ordering::SeqCst));
+  drop(get);
+  // The cancelled future is polled to its end on a worker thread.
+  let start = Instant::now();
+  while !dropped.load(Ordering::SeqCst) {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(1));
+  }
+}
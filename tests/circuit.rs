@@ -0,0 +1,264 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use thunky::{Circuit, CircuitBreaker, Decision, ManualClock, Retry};
+use thunky::{Thunky, ThunkyBuilder};
+
+const OPEN: &str = "thunky circuit breaker is open";
+
+type Transitions = Arc<Mutex<Vec<Circuit>>>;
+
+fn breaker(transitions: &Transitions) -> CircuitBreaker {
+  let transitions = Arc::clone(transitions);
+  CircuitBreaker::new(2, Duration::from_secs(30)).on_change(move |circuit| {
+    transitions.lock().unwrap().push(circuit);
+  })
+}
+
+fn build(
+  clock: &Arc<ManualClock>,
+  transitions: &Transitions,
+  pending: &Arc<Pending<u32>>
+) -> Arc<Thunky<u32, &'static str>> {
+  ThunkyBuilder::new()
+    .circuit_breaker(breaker(transitions))
+    .clock(clock.clone())
+    .build(pending.run_fn())
+}
+
+fn fail(thunk: &Thunky<u32, &'static str>, pending: &Pending<u32>) {
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+  pending.resolve(Err("down"));
+}
+
+#[test]
+fn opens_after_consecutive_failures() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = build(&clock, &transitions, &pending);
+
+  fail(&thunk, &pending);
+  assert_eq!(Circuit::Closed, thunk.circuit());
+  fail(&thunk, &pending);
+  assert_eq!(Circuit::Open, thunk.circuit());
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(29));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err(OPEN), Err(OPEN)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+  assert_eq!(vec![Circuit::Open], *transitions.lock().unwrap());
+}
+
+#[test]
+fn longest_cool_down_stays_open() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .circuit_breaker(CircuitBreaker::new(1, Duration::MAX))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  fail(&thunk, &pending);
+  clock.advance(Duration::from_secs(60 * 60 * 24 * 365));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err(OPEN)], *results.lock().unwrap());
+  assert_eq!(Circuit::Open, thunk.circuit());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn success_resets_failures() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let thunk = build(&clock, &transitions, &pending);
+
+  fail(&thunk, &pending);
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+  pending.resolve(Ok(1));
+  thunk.invalidate();
+  fail(&thunk, &pending);
+
+  assert_eq!(Circuit::Closed, thunk.circuit());
+  assert!(transitions.lock().unwrap().is_empty());
+}
+
+#[test]
+fn successful_probe_closes() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = build(&clock, &transitions, &pending);
+
+  fail(&thunk, &pending);
+  fail(&thunk, &pending);
+  clock.advance(Duration::from_secs(30));
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(Circuit::HalfOpen, thunk.circuit());
+  assert_eq!(3, pending.calls());
+
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(
+    vec![Circuit::Open, Circuit::HalfOpen, Circuit::Closed],
+    *transitions.lock().unwrap()
+  );
+}
+
+#[test]
+fn failed_probe_reopens() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = build(&clock, &transitions, &pending);
+
+  fail(&thunk, &pending);
+  fail(&thunk, &pending);
+  clock.advance(Duration::from_secs(30));
+  fail(&thunk, &pending);
+  assert_eq!(Circuit::Open, thunk.circuit());
+
+  clock.advance(Duration::from_secs(29));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err(OPEN)], *results.lock().unwrap());
+  assert_eq!(3, pending.calls());
+  assert_eq!(
+    vec![Circuit::Open, Circuit::HalfOpen, Circuit::Open],
+    *transitions.lock().unwrap()
+  );
+}
+
+#[test]
+fn cached_errors_are_not_failures() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let thunk = ThunkyBuilder::new()
+    .circuit_breaker(breaker(&transitions))
+    .clock(clock.clone())
+    .policy(|result: &Result<u32, &'static str>| match result {
+      Err("not found") => Decision::CacheFor(Duration::from_secs(1)),
+      Err(_) => Decision::Skip,
+      Ok(_) => Decision::Cache
+    })
+    .build(pending.run_fn());
+
+  for _ in 0..3 {
+    thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+    pending.resolve(Err("not found"));
+    clock.advance(Duration::from_secs(1));
+  }
+  assert_eq!(Circuit::Closed, thunk.circuit());
+}
+
+#[test]
+fn retries_count_as_one_failure() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let thunk = ThunkyBuilder::new()
+    .circuit_breaker(breaker(&transitions))
+    .retry(Retry::new(3).backoff(
+      Duration::from_secs(1),
+      Duration::from_secs(1)
+    ))
+    .clock(clock.clone())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+  for _ in 0..3 {
+    pending.resolve(Err("down"));
+    clock.advance(Duration::from_secs(1));
+  }
+  assert_eq!(3, pending.calls());
+  assert_eq!(Circuit::Closed, thunk.circuit());
+}
+
+#[test]
+fn listener_may_use_thunky() {
+  let clock = Arc::new(ManualClock::new());
+  let seen = Arc::new(Mutex::new(Vec::new()));
+  let cell = Arc::new(Mutex::new(None::<Arc<Thunky<u32, &'static str>>>));
+  let pending = Pending::new();
+
+  let seen_clone = Arc::clone(&seen);
+  let cell_clone = Arc::clone(&cell);
+  let thunk = ThunkyBuilder::new()
+    .circuit_breaker(CircuitBreaker::new(1, Duration::from_secs(30)).on_change(
+      move |_circuit| {
+        if let Some(thunk) = cell_clone.lock().unwrap().take() {
+          seen_clone.lock().unwrap().push(thunk.circuit());
+        }
+      }
+    ))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+  *cell.lock().unwrap() = Some(Arc::clone(&thunk));
+
+  fail(&thunk, &pending);
+  assert_eq!(vec![Circuit::Open], *seen.lock().unwrap());
+}
+
+#[test]
+fn without_breaker_failures_always_run() {
+  let pending = Pending::new();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  for _ in 0..5 {
+    fail(&thunk, &pending);
+  }
+  assert_eq!(5, pending.calls());
+  assert_eq!(Circuit::Closed, thunk.circuit());
+}
+
+#[test]
+fn failed_refreshes_open_the_circuit() {
+  let clock = Arc::new(ManualClock::new());
+  let transitions = Transitions::default();
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .circuit_breaker(breaker(&transitions))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+
+  fail(&thunk, &pending);
+  assert_eq!(Circuit::Closed, thunk.circuit());
+  fail(&thunk, &pending);
+  assert_eq!(Circuit::Open, thunk.circuit());
+  assert_eq!(3, pending.calls());
+
+  // The stale value is served, without calling the run function.
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(3, pending.calls());
+  assert_eq!(vec![Ok(1), Ok(1), Ok(1)], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(30));
+  thunk.run(collect(&results));
+  assert_eq!(Circuit::HalfOpen, thunk.circuit());
+  assert_eq!(4, pending.calls());
+  pending.resolve(Ok(2));
+  assert_eq!(Circuit::Closed, thunk.circuit());
+
+  thunk.run(collect(&results));
+  assert_eq!(Some(&Ok(2)), results.lock().unwrap().last());
+}
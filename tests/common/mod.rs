@@ -0,0 +1,76 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thunky::Resolver;
+
+pub type Results<T> = Arc<Mutex<Vec<Result<T, &'static str>>>>;
+pub type Callback<T> = Box<dyn Fn(&Result<T, &'static str>) + Send + Sync>;
+pub type OnceCallback<T> = Box<dyn FnOnce(&Result<T, &'static str>) + Send>;
+pub type RunFn<T> = Box<dyn Fn(Resolver<T, &'static str>) + Send + Sync>;
+
+/// A callback pushing its result onto `results`.
+pub fn collect<T>(results: &Results<T>) -> Callback<T>
+where
+  T: Clone + Send + 'static
+{
+  let results = Arc::clone(results);
+  Box::new(move |arg: &Result<T, &'static str>| {
+    results.lock().unwrap().push(arg.clone());
+  })
+}
+
+/// Wait until `results` has `len` results, for runs resolved on another
+/// thread.
+pub fn wait_for<T>(results: &Results<T>, len: usize) {
+  let start = Instant::now();
+  while results.lock().unwrap().len() < len {
+    assert!(start.elapsed() < Duration::from_secs(5));
+    thread::sleep(Duration::from_millis(1));
+  }
+}
+
+/// Run functions which keep their resolvers, for the test to resolve.
+pub struct Pending<T> {
+  calls: AtomicUsize,
+  resolvers: Mutex<VecDeque<Resolver<T, &'static str>>>
+}
+
+impl<T> Pending<T>
+where
+  T: Send + Sync + 'static
+{
+  pub fn new() -> Arc<Pending<T>> {
+    Arc::new(Pending {
+      calls: AtomicUsize::new(0),
+      resolvers: Mutex::new(VecDeque::new())
+    })
+  }
+
+  pub fn run_fn(self: &Arc<Self>) -> RunFn<T> {
+    let pending = Arc::clone(self);
+    Box::new(move |resolver: Resolver<T, &'static str>| {
+      pending.calls.fetch_add(1, Ordering::SeqCst);
+      pending.resolvers.lock().unwrap().push_back(resolver);
+    })
+  }
+
+  /// How many times the run function was called.
+  pub fn calls(&self) -> usize {
+    self.calls.load(Ordering::SeqCst)
+  }
+
+  /// Take the oldest resolver.
+  pub fn take(&self) -> Resolver<T, &'static str> {
+    self.resolvers.lock().unwrap().pop_front().unwrap()
+  }
+
+  /// Resolve the oldest resolver with `result`.
+  pub fn resolve(&self, result: Result<T, &'static str>) {
+    self.take().resolve(result);
+  }
+}
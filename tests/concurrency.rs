@@ -0,0 +1,87 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use common::{collect, Pending, Results};
+use thunky::{Resolver, ThunkyBuilder};
+
+#[test]
+fn resolved_reads_race_resets() {
+  let runs = Arc::new(AtomicU64::new(0));
+  let runs_clone = Arc::clone(&runs);
+  let thunk = ThunkyBuilder::new().build(Box::new(
+    move |resolver: Resolver<u64, &'static str>| {
+      resolver.resolve(Ok(runs_clone.fetch_add(1, Ordering::SeqCst) + 1));
+    }
+  ));
+  let done = Arc::new(AtomicBool::new(false));
+
+  let readers: Vec<_> = (0..4)
+    .map(|_| {
+      let thunk = Arc::clone(&thunk);
+      let runs = Arc::clone(&runs);
+      let done = Arc::clone(&done);
+      thread::spawn(move || {
+        while !done.load(Ordering::SeqCst) {
+          let runs = Arc::clone(&runs);
+          thunk.run(Box::new(move |arg: &Result<u64, &'static str>| {
+            let value = *arg.as_ref().unwrap();
+            assert!(value >= 1 && value <= runs.load(Ordering::SeqCst));
+          }));
+        }
+      })
+    })
+    .collect();
+
+  while runs.load(Ordering::SeqCst) < 100 {
+    thunk.reset();
+    thunk.invalidate();
+    thread::yield_now();
+  }
+  done.store(true, Ordering::SeqCst);
+  for reader in readers {
+    reader.join().unwrap();
+  }
+
+  // Once the resets stop, every reader gets the same, last value.
+  let results = Results::default();
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  let last = runs.load(Ordering::SeqCst);
+  assert_eq!(vec![Ok(last), Ok(last)], *results.lock().unwrap());
+}
+
+#[test]
+fn waiters_race_resolve() {
+  let pending = Pending::<u64>::new();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+  let results = Results::default();
+  thunk.run(collect(&results));
+
+  let waiters: Vec<_> = (0..4)
+    .map(|_| {
+      let thunk = Arc::clone(&thunk);
+      let results = Arc::clone(&results);
+      thread::spawn(move || {
+        for _ in 0..1000 {
+          thunk.run(collect(&results));
+        }
+      })
+    })
+    .collect();
+  while thunk.waiter_count() < 100 {
+    thread::yield_now();
+  }
+  pending.resolve(Ok(1));
+  for waiter in waiters {
+    waiter.join().unwrap();
+  }
+
+  // Waiters queued while the run resolves are called with its result too.
+  let results = results.lock().unwrap();
+  assert_eq!(4001, results.len());
+  assert!(results.iter().all(|result| *result == Ok(1)));
+  assert_eq!(1, pending.calls());
+}
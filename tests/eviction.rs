@@ -0,0 +1,148 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use common::{collect, Callback, Results};
+use thunky::{Eviction, Resolver, ThunkyBuilder, ThunkyMap};
+
+type Resolvers = Arc<Mutex<Vec<(u32, Resolver<u32, &'static str>)>>>;
+
+fn counting(
+  builder: ThunkyBuilder,
+  calls: &Arc<AtomicUsize>
+) -> ThunkyMap<u32, u32, &'static str> {
+  let calls = Arc::clone(calls);
+  builder.build_map(Box::new(
+    move |key: &u32, resolver: Resolver<u32, &'static str>| {
+      calls.fetch_add(1, Ordering::SeqCst);
+      resolver.resolve(Ok(*key));
+    }
+  ))
+}
+
+fn noop() -> Callback<u32> {
+  Box::new(|_arg: &Result<u32, &'static str>| {})
+}
+
+fn keys(map: &ThunkyMap<u32, u32, &'static str>) -> Vec<u32> {
+  let mut keys: Vec<u32> =
+    map.resolved().into_iter().map(|(key, _)| key).collect();
+  keys.sort_unstable();
+  keys
+}
+
+#[test]
+fn lru_evicts_least_recently_used() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let map = counting(ThunkyBuilder::new().capacity(3), &calls);
+
+  for key in &[1, 2, 3, 1, 4] {
+    map.run(*key, noop());
+  }
+  assert_eq!(vec![1, 3, 4], keys(&map));
+
+  map.run(5, noop());
+  assert_eq!(vec![1, 4, 5], keys(&map));
+  assert_eq!(5, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn lfu_evicts_least_frequently_used() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let map = counting(
+    ThunkyBuilder::new().capacity(3).eviction(Eviction::Lfu),
+    &calls
+  );
+
+  for key in &[1, 1, 1, 2, 2, 3, 3, 4] {
+    map.run(*key, noop());
+  }
+  assert_eq!(vec![1, 3, 4], keys(&map));
+
+  map.run(5, noop());
+  assert_eq!(vec![1, 3, 5], keys(&map));
+}
+
+#[test]
+fn evicted_key_reruns() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let map = counting(ThunkyBuilder::new().capacity(1), &calls);
+
+  map.run(1, collect(&results));
+  map.run(2, collect(&results));
+  map.run(1, collect(&results));
+  assert_eq!(vec![Ok(1), Ok(2), Ok(1)], *results.lock().unwrap());
+  assert_eq!(3, calls.load(Ordering::SeqCst));
+  assert_eq!(1, map.len());
+}
+
+#[test]
+fn eviction_drops_cached_value() {
+  let value = Arc::new(());
+
+  let value_clone = Arc::clone(&value);
+  let map = ThunkyBuilder::new().capacity(1).build_map(Box::new(
+    move |_key: &u32, resolver: Resolver<Arc<()>, &'static str>| {
+      resolver.resolve(Ok(Arc::clone(&value_clone)));
+    }
+  ));
+
+  map.run(1, Box::new(|_arg: &Result<Arc<()>, &'static str>| {}));
+  assert_eq!(3, Arc::strong_count(&value));
+
+  map.remove(&1);
+  assert_eq!(2, Arc::strong_count(&value));
+
+  map.run(1, Box::new(|_arg: &Result<Arc<()>, &'static str>| {}));
+  map.run(2, Box::new(|_arg: &Result<Arc<()>, &'static str>| {}));
+  assert_eq!(3, Arc::strong_count(&value));
+}
+
+#[test]
+fn running_keys_are_not_evicted() {
+  let resolvers = Resolvers::default();
+  let results = Results::default();
+
+  let resolvers_clone = Arc::clone(&resolvers);
+  let map = ThunkyBuilder::new().capacity(2).build_map(Box::new(
+    move |key: &u32, resolver: Resolver<u32, &'static str>| {
+      resolvers_clone.lock().unwrap().push((*key, resolver));
+    }
+  ));
+
+  map.run(1, collect(&results));
+  map.run(2, collect(&results));
+  map.run(3, collect(&results));
+  assert_eq!(3, map.len());
+
+  for (key, resolver) in resolvers.lock().unwrap().drain(..) {
+    resolver.resolve(Ok(key));
+  }
+  assert_eq!(vec![Ok(1), Ok(2), Ok(3)], *results.lock().unwrap());
+
+  map.run(4, collect(&results));
+  assert_eq!(2, map.len());
+  assert!(!map.contains_key(&1));
+  assert!(!map.contains_key(&2));
+  assert!(map.contains_key(&3));
+}
+
+#[test]
+fn failed_keys_are_evicted() {
+  let resolvers = Resolvers::default();
+
+  let resolvers_clone = Arc::clone(&resolvers);
+  let map = ThunkyBuilder::new().capacity(1).build_map(Box::new(
+    move |key: &u32, resolver: Resolver<u32, &'static str>| {
+      resolvers_clone.lock().unwrap().push((*key, resolver));
+    }
+  ));
+
+  map.run(1, noop());
+  resolvers.lock().unwrap().pop().unwrap().1.resolve(Err("down"));
+  map.run(2, noop());
+  assert!(!map.contains_key(&1));
+  assert!(map.contains_key(&2));
+}
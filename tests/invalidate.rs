@@ -0,0 +1,174 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{collect, wait_for, Pending, Results};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+use thunky::{ManualClock, Resolver, Thunky, ThunkyBuilder};
+
+#[test]
+fn invalidate_finished_reruns() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  thunk.invalidate();
+
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(2));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(2), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn invalidate_keeps_run_in_progress() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  let generation = thunk.generation();
+  thunk.invalidate();
+  assert_eq!(generation, thunk.generation());
+
+  pending.resolve(Ok(1));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn invalidate_while_revalidating_waits_for_refresh() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  thunk.invalidate();
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+
+  pending.resolve(Ok(2));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn reset_ignores_superseded_cache() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  thunk.reset();
+
+  assert_eq!(2, pending.calls());
+  let first = pending.take();
+  let second = pending.take();
+  assert_ne!(first.generation(), second.generation());
+  assert_eq!(thunk.generation(), second.generation());
+
+  let superseded = first.generation();
+  first.resolve(Ok(1));
+  assert!(results.lock().unwrap().is_empty());
+
+  second.resolve(Ok(2));
+  thunk.cache_for(superseded, Ok(1));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(2), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn reset_without_waiters_rearms_run() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  let before = thunk.generation();
+  thunk.reset();
+  assert_eq!(1, pending.calls());
+
+  thunk.cache_for(before, Ok(3));
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(2));
+  assert_eq!(vec![Ok(1), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn reset_supersedes_async_init() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let senders = Arc::new(Mutex::new(Vec::new()));
+
+  let calls_clone = Arc::clone(&calls);
+  let senders_clone = Arc::clone(&senders);
+  let thunk = Thunky::from_async(move || {
+    calls_clone.fetch_add(1, Ordering::SeqCst);
+    let (tx, rx) = oneshot::channel::<u32>();
+    senders_clone.lock().unwrap().push(tx);
+    async move { rx.await.map_err(|_| "canceled") }
+  });
+
+  let results = Results::default();
+  thunk.run(collect(&results));
+  thunk.reset();
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+
+  let mut senders = senders.lock().unwrap();
+  senders.remove(0).send(1).unwrap();
+  senders.remove(0).send(2).unwrap();
+  wait_for(&results, 1);
+  assert_eq!(Ok(2), block_on(thunk.get()));
+  assert_eq!(vec![Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn invalidate_drops_value_while_other_callbacks_run() {
+  let value = Arc::new(());
+  let value_clone = Arc::clone(&value);
+  let thunk = Thunky::new(move |resolver: Resolver<Arc<()>, &'static str>| {
+    resolver.resolve(Ok(Arc::clone(&value_clone)))
+  });
+  let other = Thunky::new(|resolver: Resolver<u32, &'static str>| {
+    resolver.resolve(Ok(1))
+  });
+  other.run(|_: &Result<u32, &'static str>| {});
+
+  let (entered, entering) = mpsc::channel();
+  let (release, released) = mpsc::channel::<()>();
+  let reading = {
+    let thunk = Arc::clone(&thunk);
+    thread::spawn(move || {
+      thunk.run(|_: &Result<Arc<()>, &'static str>| {});
+      other.run(move |_: &Result<u32, &'static str>| {
+        entered.send(()).unwrap();
+        released.recv().unwrap();
+      });
+    })
+  };
+  entering.recv().unwrap();
+  assert_eq!(3, Arc::strong_count(&value));
+
+  thunk.invalidate();
+  assert_eq!(2, Arc::strong_count(&value));
+  release.send(()).unwrap();
+  reading.join().unwrap();
+}
@@ -0,0 +1,169 @@
+//! Model checks of the state machine, run with
+//! `RUSTFLAGS="--cfg thunky_loom" cargo test --release --test loom`.
+
+#![cfg(thunky_loom)]
+
+use std::sync::Arc;
+
+use loom::sync::atomic::{AtomicUsize, Ordering};
+use loom::sync::Mutex;
+use loom::thread;
+use thunky::{Resolver, Thunky, ThunkyBuilder};
+
+type Results = Arc<Mutex<Vec<Result<usize, &'static str>>>>;
+type Callback = Box<dyn Fn(&Result<usize, &'static str>) + Send + Sync>;
+
+fn collect(results: &Results) -> Callback {
+  let results = Arc::clone(results);
+  Box::new(move |arg: &Result<usize, &'static str>| {
+    results.lock().unwrap().push(*arg);
+  })
+}
+
+/// A thunky resolving every run right away with the number of runs so far.
+fn counting() -> (Arc<Thunky<usize, &'static str>>, Arc<AtomicUsize>) {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let calls_clone = Arc::clone(&calls);
+  let thunk = ThunkyBuilder::new().build(Box::new(
+    move |resolver: Resolver<usize, &'static str>| {
+      resolver.resolve(Ok(calls_clone.fetch_add(1, Ordering::SeqCst) + 1));
+    }
+  ));
+  (thunk, calls)
+}
+
+#[test]
+fn concurrent_runs_init_once() {
+  loom::model(|| {
+    let (thunk, calls) = counting();
+    let results = Results::default();
+
+    let threads: Vec<_> = (0..2)
+      .map(|_| {
+        let thunk = Arc::clone(&thunk);
+        let results = Arc::clone(&results);
+        thread::spawn(move || thunk.run(collect(&results)))
+      })
+      .collect();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  });
+}
+
+#[test]
+fn resolve_races_run() {
+  loom::model(|| {
+    let resolvers = Arc::new(Mutex::new(Vec::new()));
+    let resolvers_clone = Arc::clone(&resolvers);
+    let thunk = ThunkyBuilder::new().build(Box::new(
+      move |resolver: Resolver<usize, &'static str>| {
+        resolvers_clone.lock().unwrap().push(resolver);
+      }
+    ));
+    let results = Results::default();
+
+    thunk.run(collect(&results));
+    let resolver = resolvers.lock().unwrap().pop().unwrap();
+    let resolving = thread::spawn(move || resolver.resolve(Ok(1)));
+    let running = {
+      let thunk = Arc::clone(&thunk);
+      let results = Arc::clone(&results);
+      thread::spawn(move || thunk.run(collect(&results)))
+    };
+    resolving.join().unwrap();
+    running.join().unwrap();
+
+    assert!(resolvers.lock().unwrap().is_empty());
+    assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  });
+}
+
+#[test]
+fn error_races_run() {
+  loom::model(|| {
+    let resolvers = Arc::new(Mutex::new(Vec::new()));
+    let resolvers_clone = Arc::clone(&resolvers);
+    let thunk = ThunkyBuilder::new().build(Box::new(
+      move |resolver: Resolver<usize, &'static str>| {
+        resolvers_clone.lock().unwrap().push(resolver);
+      }
+    ));
+    let results = Results::default();
+
+    thunk.run(collect(&results));
+    let resolver = resolvers.lock().unwrap().pop().unwrap();
+    let resolving = thread::spawn(move || resolver.resolve(Err("down")));
+    let running = {
+      let thunk = Arc::clone(&thunk);
+      let results = Arc::clone(&results);
+      thread::spawn(move || thunk.run(collect(&results)))
+    };
+    resolving.join().unwrap();
+    running.join().unwrap();
+
+    // The second callback either got the error, or started a new run.
+    let results = results.lock().unwrap();
+    let rerun = resolvers.lock().unwrap().len();
+    assert_eq!(2 - rerun, results.len());
+    assert!(results.iter().all(|result| *result == Err("down")));
+  });
+}
+
+#[test]
+fn reset_races_resolved_run() {
+  loom::model(|| {
+    let (thunk, calls) = counting();
+    let results = Results::default();
+    thunk.run(collect(&results));
+
+    let resetting = {
+      let thunk = Arc::clone(&thunk);
+      thread::spawn(move || thunk.reset())
+    };
+    let running = {
+      let thunk = Arc::clone(&thunk);
+      let results = Arc::clone(&results);
+      thread::spawn(move || thunk.run(collect(&results)))
+    };
+    resetting.join().unwrap();
+    running.join().unwrap();
+
+    let results = results.lock().unwrap();
+    assert_eq!(2, results.len());
+    assert_eq!(Ok(calls.load(Ordering::SeqCst)), results[1]);
+  });
+}
+
+#[test]
+fn invalidate_races_resolved_runs() {
+  loom::model(|| {
+    let (thunk, calls) = counting();
+    let results = Results::default();
+    thunk.run(collect(&results));
+
+    let invalidating = {
+      let thunk = Arc::clone(&thunk);
+      thread::spawn(move || thunk.invalidate())
+    };
+    let running = {
+      let thunk = Arc::clone(&thunk);
+      let results = Arc::clone(&results);
+      thread::spawn(move || {
+        thunk.run(collect(&results));
+        thunk.run(collect(&results));
+      })
+    };
+    invalidating.join().unwrap();
+    running.join().unwrap();
+
+    // Once a run sees the new value, it never sees the old one again.
+    let results = results.lock().unwrap();
+    assert_eq!(3, results.len());
+    assert!(results[1] <= results[2]);
+    assert!(calls.load(Ordering::SeqCst) <= 2);
+  });
+}
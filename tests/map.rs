@@ -0,0 +1,197 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{collect, Results};
+use futures::executor::block_on;
+use thunky::{ManualClock, Resolver, ThunkyBuilder, ThunkyMap};
+
+type Resolvers = Arc<Mutex<Vec<(u32, Resolver<u32, &'static str>)>>>;
+
+/// A map whose run function keeps the resolvers along with their key.
+fn deferred(resolvers: &Resolvers) -> ThunkyMap<u32, u32, &'static str> {
+  let resolvers = Arc::clone(resolvers);
+  ThunkyMap::new(Box::new(
+    move |key: &u32, resolver: Resolver<u32, &'static str>| {
+      resolvers.lock().unwrap().push((*key, resolver));
+    }
+  ))
+}
+
+fn resolve(resolvers: &Resolvers, key: u32, result: Result<u32, &'static str>) {
+  let mut resolvers = resolvers.lock().unwrap();
+  let index = resolvers.iter().position(|(k, _)| *k == key).unwrap();
+  resolvers.remove(index).1.resolve(result);
+}
+
+#[test]
+fn concurrent_runs_share_one_init_per_key() {
+  let resolvers = Resolvers::default();
+  let results = Results::default();
+  let map = deferred(&resolvers);
+
+  map.run(1, collect(&results));
+  map.run(1, collect(&results));
+  map.run(2, collect(&results));
+  assert_eq!(2, resolvers.lock().unwrap().len());
+
+  resolve(&resolvers, 1, Ok(10));
+  assert_eq!(vec![Ok(10), Ok(10)], *results.lock().unwrap());
+
+  resolve(&resolvers, 2, Ok(20));
+  map.run(1, collect(&results));
+  assert_eq!(
+    vec![Ok(10), Ok(10), Ok(20), Ok(10)],
+    *results.lock().unwrap()
+  );
+  assert!(resolvers.lock().unwrap().is_empty());
+}
+
+#[test]
+fn err_rearms_only_its_key() {
+  let resolvers = Resolvers::default();
+  let results = Results::default();
+  let map = deferred(&resolvers);
+
+  map.run(1, collect(&results));
+  map.run(2, collect(&results));
+  resolve(&resolvers, 1, Err("down"));
+  resolve(&resolvers, 2, Ok(20));
+
+  map.run(1, collect(&results));
+  map.run(2, collect(&results));
+  let pending: Vec<u32> =
+    resolvers.lock().unwrap().iter().map(|(key, _)| *key).collect();
+  assert_eq!(vec![1], pending);
+  assert_eq!(vec![Err("down"), Ok(20), Ok(20)], *results.lock().unwrap());
+}
+
+#[test]
+fn remove_reruns_on_next_run() {
+  let resolvers = Resolvers::default();
+  let results = Results::default();
+  let map = deferred(&resolvers);
+
+  map.run(1, collect(&results));
+  resolve(&resolvers, 1, Ok(10));
+  assert!(map.contains_key(&1));
+
+  assert!(map.remove(&1).is_some());
+  assert!(map.remove(&1).is_none());
+  assert!(map.is_empty());
+
+  map.run(1, collect(&results));
+  resolve(&resolvers, 1, Ok(11));
+  assert_eq!(vec![Ok(10), Ok(11)], *results.lock().unwrap());
+}
+
+#[test]
+fn removed_thunky_kept_alive_is_resolved() {
+  let resolvers = Resolvers::default();
+  let results = Results::default();
+  let map = deferred(&resolvers);
+
+  map.run(1, collect(&results));
+  let thunky = map.remove(&1).unwrap();
+  resolve(&resolvers, 1, Ok(10));
+
+  assert_eq!(vec![Ok(10)], *results.lock().unwrap());
+  assert_eq!(Ok(10), block_on(thunky.get()));
+}
+
+#[test]
+fn resolved_lists_cached_entries() {
+  let resolvers = Resolvers::default();
+  let map = deferred(&resolvers);
+  let noop = || Box::new(|_arg: &Result<u32, &'static str>| {});
+
+  map.run(1, noop());
+  map.run(2, noop());
+  map.run(3, noop());
+  resolve(&resolvers, 1, Ok(10));
+  resolve(&resolvers, 2, Err("down"));
+
+  let resolved: Vec<(u32, Result<u32, &str>)> = map
+    .resolved()
+    .into_iter()
+    .map(|(key, result)| (key, *result))
+    .collect();
+  assert_eq!(vec![(1, Ok(10))], resolved);
+  assert_eq!(3, map.len());
+}
+
+#[test]
+fn resolved_leaves_out_expired_entries() {
+  let clock = Arc::new(ManualClock::new());
+  let map = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build_map(Box::new(|key: &u32, resolver: Resolver<u32, &str>| {
+      resolver.resolve(Ok(*key));
+    }));
+
+  map.run(1, Box::new(|_arg: &Result<u32, &str>| {}));
+  clock.advance(Duration::from_secs(5));
+  map.run(2, Box::new(|_arg: &Result<u32, &str>| {}));
+  clock.advance(Duration::from_secs(5));
+
+  let keys: Vec<u32> = map.resolved().into_iter().map(|(key, _)| key).collect();
+  assert_eq!(vec![2], keys);
+}
+
+#[test]
+fn run_from_callback_uses_map() {
+  let map = Arc::new(ThunkyMap::new(Box::new(
+    |key: &u32, resolver: Resolver<u32, &'static str>| {
+      resolver.resolve(Ok(*key * 10));
+    }
+  )));
+  let results = Results::default();
+
+  let map_clone = Arc::clone(&map);
+  let results_clone = Arc::clone(&results);
+  map.run(1, Box::new(move |arg: &Result<u32, &'static str>| {
+    results_clone.lock().unwrap().push(*arg);
+    map_clone.run(2, collect(&results_clone));
+  }));
+
+  assert_eq!(vec![Ok(10), Ok(20)], *results.lock().unwrap());
+}
+
+#[test]
+fn threads_share_one_init_per_key() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let map = Arc::new(ThunkyMap::new(Box::new(
+    move |key: &u32, resolver: Resolver<u32, &'static str>| {
+      calls_clone.fetch_add(1, Ordering::SeqCst);
+      let key = *key;
+      thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        resolver.resolve(Ok(key));
+      });
+    }
+  )));
+
+  let barrier = Arc::new(Barrier::new(8));
+  let threads: Vec<_> = (0..8)
+    .map(|i| {
+      let map = Arc::clone(&map);
+      let barrier = Arc::clone(&barrier);
+      thread::spawn(move || {
+        barrier.wait();
+        let key = i % 2;
+        assert_eq!(Ok(key), block_on(map.entry(key).get()));
+      })
+    })
+    .collect();
+
+  for thread in threads {
+    thread.join().unwrap();
+  }
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+}
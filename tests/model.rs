@@ -0,0 +1,182 @@
+//! Random sequences of operations on a thunky, checked against a reference
+//! model of the documented `Run`/`Wait`/`Finish` semantics.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use proptest::prelude::*;
+use thunky::{Resolver, ThunkyBuilder};
+
+type Value = Result<u8, &'static str>;
+type Deliveries = Vec<(usize, Value)>;
+
+#[derive(Clone, Debug)]
+enum Op {
+  /// `thunk.run()` with a new callback.
+  Run,
+  /// Resolve the oldest resolver the run function kept.
+  Resolve(Value),
+  /// `thunk.cache()`.
+  Cache(Value),
+  /// Make the run function resolve right away with the value, or keep its
+  /// resolver.
+  Sync(Option<Value>),
+  /// `thunk.invalidate()`.
+  Invalidate
+}
+
+fn value() -> impl Strategy<Value = Value> {
+  prop_oneof![
+    any::<u8>().prop_map(Ok),
+    prop_oneof![Just("a"), Just("b")].prop_map(Err)
+  ]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+  prop_oneof![
+    4 => Just(Op::Run),
+    3 => value().prop_map(Op::Resolve),
+    1 => value().prop_map(Op::Cache),
+    1 => proptest::option::of(value()).prop_map(Op::Sync),
+    1 => Just(Op::Invalidate)
+  ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+  Run,
+  Wait,
+  Finish
+}
+
+/// What `thunk.run()` and `thunk.cache()` are documented to do.
+struct Model {
+  state: State,
+  cache: Option<Value>,
+  queue: Vec<usize>,
+  /// Resolvers kept by the run function, not resolved yet.
+  pending: usize,
+  sync: Option<Value>,
+  deliveries: Deliveries
+}
+
+impl Model {
+  fn new() -> Model {
+    Model {
+      state: State::Run,
+      cache: None,
+      queue: Vec::new(),
+      pending: 0,
+      sync: None,
+      deliveries: Vec::new()
+    }
+  }
+
+  fn run(&mut self, id: usize) {
+    match self.state {
+      State::Finish => {
+        self.deliveries.push((id, self.cache.unwrap()));
+      },
+      State::Wait => self.queue.push(id),
+      State::Run => {
+        self.queue.push(id);
+        self.state = State::Wait;
+        match self.sync {
+          Some(value) => self.cache(value),
+          None => self.pending += 1
+        }
+      }
+    }
+  }
+
+  /// Callbacks are called in the order of `run()`.
+  fn cache(&mut self, value: Value) {
+    if self.state == State::Finish {
+      return;
+    }
+    for id in self.queue.drain(..) {
+      self.deliveries.push((id, value));
+    }
+    if value.is_ok() {
+      self.cache = Some(value);
+      self.state = State::Finish;
+    } else {
+      self.state = State::Run;
+    }
+  }
+
+  fn resolve(&mut self, value: Value) {
+    if self.pending > 0 {
+      self.pending -= 1;
+      self.cache(value);
+    }
+  }
+
+  fn invalidate(&mut self) {
+    if self.state == State::Finish {
+      self.cache = None;
+      self.state = State::Run;
+    }
+  }
+}
+
+fn check(ops: Vec<Op>) -> Result<(), TestCaseError> {
+  let resolvers = Arc::new(Mutex::new(VecDeque::new()));
+  let sync = Arc::new(Mutex::new(None));
+  let deliveries = Arc::new(Mutex::new(Deliveries::new()));
+
+  let resolvers_clone = Arc::clone(&resolvers);
+  let sync_clone = Arc::clone(&sync);
+  let thunk = ThunkyBuilder::new().build(Box::new(
+    move |resolver: Resolver<u8, &'static str>| {
+      let value = *sync_clone.lock().unwrap();
+      match value {
+        Some(value) => resolver.resolve(value),
+        None => resolvers_clone.lock().unwrap().push_back(resolver)
+      }
+    }
+  ));
+
+  let mut model = Model::new();
+  for (id, op) in ops.into_iter().enumerate() {
+    match op {
+      Op::Run => {
+        let deliveries = Arc::clone(&deliveries);
+        thunk.run(Box::new(move |arg: &Value| {
+          deliveries.lock().unwrap().push((id, *arg));
+        }));
+        model.run(id);
+      },
+      Op::Resolve(value) => {
+        let resolver = resolvers.lock().unwrap().pop_front();
+        if let Some(resolver) = resolver {
+          resolver.resolve(value);
+        }
+        model.resolve(value);
+      },
+      Op::Cache(value) => {
+        thunk.cache(value);
+        model.cache(value);
+      },
+      Op::Sync(value) => {
+        *sync.lock().unwrap() = value;
+        model.sync = value;
+      },
+      Op::Invalidate => {
+        thunk.invalidate();
+        model.invalidate();
+      }
+    }
+
+    prop_assert_eq!(&model.deliveries, &*deliveries.lock().unwrap());
+    prop_assert_eq!(model.pending, resolvers.lock().unwrap().len());
+  }
+  Ok(())
+}
+
+proptest! {
+  #[test]
+  fn deliveries_match_model(ops in proptest::collection::vec(op(), 1..64)) {
+    check(ops)?;
+  }
+}
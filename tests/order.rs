@@ -0,0 +1,75 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::Pending;
+use thunky::{Resolver, Thunky};
+
+type Order = Arc<Mutex<Vec<&'static str>>>;
+
+fn record(
+  order: &Order,
+  name: &'static str
+) -> impl FnOnce(&Result<u32, &'static str>) + Send + 'static {
+  let order = Arc::clone(order);
+  move |_: &Result<u32, &'static str>| order.lock().unwrap().push(name)
+}
+
+#[test]
+fn waiters_are_called_in_order() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+  let order = Order::default();
+
+  for name in ["a", "b", "c", "d"] {
+    thunk.run(record(&order, name));
+  }
+  pending.resolve(Ok(1));
+  assert_eq!(vec!["a", "b", "c", "d"], *order.lock().unwrap());
+}
+
+#[test]
+fn errors_are_delivered_in_order() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+  let order = Order::default();
+
+  for name in ["a", "b", "c"] {
+    thunk.run(record(&order, name));
+  }
+  pending.resolve(Err("fail"));
+  assert_eq!(vec!["a", "b", "c"], *order.lock().unwrap());
+}
+
+#[test]
+fn higher_priority_goes_first() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+  let order = Order::default();
+
+  thunk.run(record(&order, "a"));
+  thunk.run_with_priority(-1, record(&order, "low"));
+  thunk.run_with_priority(5, record(&order, "high 1"));
+  thunk.run(record(&order, "b"));
+  thunk.run_with_priority(5, record(&order, "high 2"));
+  thunk.run_with_priority(9, record(&order, "urgent"));
+  pending.resolve(Ok(1));
+
+  assert_eq!(
+    vec!["urgent", "high 1", "high 2", "a", "b", "low"],
+    *order.lock().unwrap()
+  );
+}
+
+#[test]
+fn resolved_thunky_ignores_priority() {
+  let thunk = Thunky::new(|resolver: Resolver<u32, &'static str>| {
+    resolver.resolve(Ok(1))
+  });
+  let order = Order::default();
+
+  thunk.run_with_priority(-1, record(&order, "low"));
+  thunk.run(record(&order, "a"));
+  thunk.run_with_priority(9, record(&order, "urgent"));
+  assert_eq!(vec!["low", "a", "urgent"], *order.lock().unwrap());
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use thunky::{Decision, ManualClock, Resolver, ThunkyBuilder};
+
+/// Cache "not found" for a minute, retry other errors, skip zeroes.
+fn policy(result: &Result<u32, &'static str>) -> Decision {
+  match result {
+    Ok(0) => Decision::Skip,
+    Ok(_) => Decision::Cache,
+    Err("not found") => Decision::CacheFor(Duration::from_secs(60)),
+    Err(_) => Decision::Skip
+  }
+}
+
+#[test]
+fn negative_result_is_cached_for_its_duration() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .clock(clock.clone())
+    .policy(policy)
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("not found"));
+  clock.advance(Duration::from_secs(59));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err("not found"), Err("not found")], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+
+  clock.advance(Duration::from_secs(1));
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(1));
+  assert_eq!(Ok(1), results.lock().unwrap()[2]);
+}
+
+#[test]
+fn transient_error_is_retried() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().policy(policy).build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("timeout"));
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(1));
+
+  assert_eq!(vec![Err("timeout"), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn skipped_ok_is_delivered_but_not_cached() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().policy(policy).build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  pending.resolve(Ok(0));
+  assert_eq!(vec![Ok(0), Ok(0)], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(2));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(0), Ok(0), Ok(2), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn cached_error_uses_ttl_without_duration() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .policy(|_result: &Result<u32, &'static str>| Decision::Cache)
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  thunk.run(collect(&results));
+  assert_eq!(1, pending.calls());
+
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn skipped_refresh_keeps_stale_value() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .clock(clock.clone())
+    .policy(policy)
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  pending.resolve(Ok(0));
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(3, pending.calls());
+
+  pending.resolve(Err("not found"));
+  thunk.run(collect(&results));
+  assert_eq!(Err("not found"), results.lock().unwrap()[3]);
+}
+
+#[test]
+fn map_thunkies_share_policy() {
+  let map = ThunkyBuilder::new().policy(policy).build_map(Box::new(
+    |key: &u32, resolver: Resolver<u32, &'static str>| {
+      if *key == 0 {
+        resolver.resolve(Err("not found"));
+      } else {
+        resolver.resolve(Ok(*key));
+      }
+    }
+  ));
+
+  for key in 0..3 {
+    map.run(key, Box::new(|_arg: &Result<u32, &'static str>| {}));
+  }
+
+  let mut resolved: Vec<(u32, Result<u32, &str>)> = map
+    .resolved()
+    .into_iter()
+    .map(|(key, result)| (key, *result))
+    .collect();
+  resolved.sort_unstable();
+  assert_eq!(
+    vec![(0, Err("not found")), (1, Ok(1)), (2, Ok(2))],
+    resolved
+  );
+}
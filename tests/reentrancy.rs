@@ -0,0 +1,149 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+
+use common::{collect, Pending, Results};
+use futures::executor::block_on;
+use thunky::{Resolver, Thunky};
+
+type Cell = Arc<OnceLock<Weak<Thunky<u32, &'static str>>>>;
+
+fn this(cell: &Cell) -> Arc<Thunky<u32, &'static str>> {
+  cell.get().and_then(Weak::upgrade).unwrap()
+}
+
+#[test]
+fn run_from_run_function_is_queued() {
+  let results = Results::default();
+
+  let cell = Cell::default();
+
+  let cell_clone = Arc::clone(&cell);
+  let results_clone = Arc::clone(&results);
+  let thunk = Thunky::new(Box::new(
+    move |resolver: Resolver<u32, &'static str>| {
+      this(&cell_clone).run(collect(&results_clone));
+      assert!(results_clone.lock().unwrap().is_empty());
+      resolver.resolve(Ok(1));
+    }
+  ));
+  cell.set(Arc::downgrade(&thunk)).unwrap();
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn run_from_run_function_without_cache() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let cell = Cell::default();
+
+  let run = pending.run_fn();
+  let cell_clone = Arc::clone(&cell);
+  let results_clone = Arc::clone(&results);
+  let thunk = Thunky::new(Box::new(
+    move |resolver: Resolver<u32, &'static str>| {
+      run(resolver);
+      this(&cell_clone).run(collect(&results_clone));
+    }
+  ));
+  cell.set(Arc::downgrade(&thunk)).unwrap();
+
+  thunk.run(collect(&results));
+  assert!(results.lock().unwrap().is_empty());
+
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn run_from_callback_in_finish() {
+  let results = Results::default();
+  let thunk = Thunky::new(Box::new(
+    |resolver: Resolver<u32, &'static str>| resolver.resolve(Ok(1))
+  ));
+
+  let results_clone = Arc::clone(&results);
+  let thunk_clone = Arc::clone(&thunk);
+  thunk.run(Box::new(move |arg: &Result<u32, &'static str>| {
+    results_clone.lock().unwrap().push(*arg);
+    thunk_clone.run(collect(&results_clone));
+  }));
+
+  let results_clone = Arc::clone(&results);
+  let thunk_clone = Arc::clone(&thunk);
+  thunk.run(Box::new(move |_arg: &Result<u32, &'static str>| {
+    thunk_clone.run(collect(&results_clone));
+  }));
+
+  assert_eq!(vec![Ok(1), Ok(1), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn run_from_callback_retries_after_err() {
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = Thunky::new(Box::new(
+    move |resolver: Resolver<u32, &'static str>| {
+      if calls_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+        resolver.resolve(Err("stop"));
+      } else {
+        resolver.resolve(Ok(2));
+      }
+    }
+  ));
+
+  let results_clone = Arc::clone(&results);
+  let thunk_clone = Arc::clone(&thunk);
+  thunk.run(Box::new(move |arg: &Result<u32, &'static str>| {
+    results_clone.lock().unwrap().push(*arg);
+    if arg.is_err() {
+      thunk_clone.run(collect(&results_clone));
+    }
+  }));
+
+  assert_eq!(vec![Err("stop"), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn cache_from_callback() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  let results_clone = Arc::clone(&results);
+  let thunk_clone = Arc::clone(&thunk);
+  thunk.run(Box::new(move |arg: &Result<u32, &'static str>| {
+    results_clone.lock().unwrap().push(*arg);
+    if arg.is_err() {
+      thunk_clone.cache(Ok(1));
+    }
+  }));
+
+  pending.resolve(Err("stop"));
+  thunk.run(collect(&results));
+
+  assert_eq!(vec![Err("stop"), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn get_from_callback() {
+  let results = Results::default();
+  let thunk = Thunky::new(Box::new(
+    |resolver: Resolver<u32, &'static str>| resolver.resolve(Ok(1))
+  ));
+
+  let results_clone = Arc::clone(&results);
+  let thunk_clone = Arc::clone(&thunk);
+  thunk.run(Box::new(move |_arg: &Result<u32, &'static str>| {
+    results_clone.lock().unwrap().push(block_on(thunk_clone.get()));
+  }));
+
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
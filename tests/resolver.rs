@@ -0,0 +1,106 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::{collect, Pending, Results};
+use futures::executor::block_on;
+use thunky::{Error, Resolver, Thunky};
+
+fn dropped() -> &'static str {
+  Error::Dropped.into()
+}
+
+#[test]
+fn dropped_resolver_delivers_error() {
+  let results = Results::<u32>::default();
+  let thunk = Thunky::new(Box::new(|_resolver: Resolver<u32, &'static str>| {}));
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Err(dropped()), Err(dropped())], *results.lock().unwrap());
+}
+
+#[test]
+fn dropped_resolver_rearms_run() {
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(collect(&results));
+  drop(pending.take());
+  assert_eq!(vec![Err(dropped())], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Err(dropped()), Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn resolver_moves_to_another_thread() {
+  let results = Results::default();
+  let thunk = Thunky::new(Box::new(
+    |resolver: Resolver<u32, &'static str>| {
+      thread::spawn(move || resolver.resolve(Ok(1))).join().unwrap();
+    }
+  ));
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn resolver_outliving_thunky_is_ignored() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {
+    panic!("thunky was dropped");
+  }));
+  drop(thunk);
+
+  pending.resolve(Ok(1));
+}
+
+#[test]
+fn generation_of_resolver() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+  assert_eq!(thunk.generation(), pending.take().generation());
+}
+
+#[test]
+fn dropped_async_init_delivers_error() {
+  let thunk = Thunky::from_async(futures::future::pending::<Result<u32, &str>>);
+  assert_eq!(Err(dropped()), block_on(thunk.get()));
+}
+
+#[test]
+fn resolve_from_callback_of_other_thunky() {
+  let resolvers = Arc::new(Mutex::new(Vec::new()));
+  let results = Results::default();
+
+  let resolvers_clone = Arc::clone(&resolvers);
+  let first = Thunky::new(Box::new(
+    move |resolver: Resolver<u32, &'static str>| {
+      resolvers_clone.lock().unwrap().push(resolver);
+    }
+  ));
+  let second = Thunky::new(Box::new(
+    |resolver: Resolver<u32, &'static str>| resolver.resolve(Ok(2))
+  ));
+
+  first.run(collect(&results));
+  let resolver = resolvers.lock().unwrap().pop().unwrap();
+  let resolver = Mutex::new(Some(resolver));
+  second.run(Box::new(move |arg: &Result<u32, &'static str>| {
+    if let Some(resolver) = resolver.lock().unwrap().take() {
+      resolver.resolve(*arg);
+    }
+  }));
+
+  assert_eq!(vec![Ok(2)], *results.lock().unwrap());
+}
@@ -0,0 +1,242 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use futures::executor::block_on;
+use thunky::{Decision, ManualClock, Resolver, Retry, ThunkyBuilder};
+
+fn retry() -> Retry {
+  Retry::new(3).backoff(Duration::from_secs(1), Duration::from_secs(10))
+}
+
+#[test]
+fn final_error_is_delivered_after_max_attempts() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  pending.resolve(Err("down 1"));
+  assert_eq!(1, pending.calls());
+
+  clock.advance(Duration::from_millis(999));
+  assert_eq!(1, pending.calls());
+  clock.advance(Duration::from_millis(1));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Err("down 2"));
+
+  clock.advance(Duration::from_millis(1999));
+  assert_eq!(2, pending.calls());
+  clock.advance(Duration::from_millis(1));
+  assert_eq!(3, pending.calls());
+  assert!(results.lock().unwrap().is_empty());
+
+  pending.resolve(Err("down 3"));
+  assert_eq!(vec![Err("down 3"), Err("down 3")], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(4, pending.calls());
+}
+
+#[test]
+fn ok_ends_retries() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  clock.advance(Duration::from_secs(1));
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+
+  thunk.invalidate();
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  clock.advance(Duration::from_secs(1));
+  pending.resolve(Err("down"));
+  clock.advance(Duration::from_secs(2));
+  assert_eq!(5, pending.calls());
+  assert_eq!(1, results.lock().unwrap().len());
+}
+
+#[test]
+fn waiters_queue_during_backoff() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  thunk.run(collect(&results));
+  assert_eq!(1, pending.calls());
+
+  clock.advance(Duration::from_secs(1));
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn cached_error_is_not_retried() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .policy(|result: &Result<u32, &'static str>| match result {
+      Err("not found") => Decision::Cache,
+      Err(_) => Decision::Skip,
+      Ok(_) => Decision::Cache
+    })
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("not found"));
+  assert_eq!(vec![Err("not found")], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(60));
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn reset_during_backoff_cancels_retry() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  thunk.reset();
+  assert_eq!(2, pending.calls());
+
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn cache_during_backoff_cancels_retry() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  thunk.cache(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn jitter_shortens_delay() {
+  let retry = retry().jitter(0.5);
+
+  for attempt in 1..5 {
+    let max = Retry::new(5)
+      .backoff(Duration::from_secs(1), Duration::from_secs(10))
+      .delay(attempt);
+    let delay = retry.delay(attempt);
+    assert!(delay <= max);
+    assert!(delay >= max / 2);
+  }
+}
+
+#[test]
+fn thread_timer_retries() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = ThunkyBuilder::new()
+    .retry(Retry::new(2).backoff(
+      Duration::from_millis(10),
+      Duration::from_millis(10)
+    ))
+    .build(Box::new(move |resolver: Resolver<u32, &'static str>| {
+      calls_clone.fetch_add(1, Ordering::SeqCst);
+      resolver.resolve(Err("down"));
+    }));
+
+  assert_eq!(Err("down"), block_on(thunk.get()));
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn err_cached_between_runs_is_not_retried() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .retry(retry())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.cache(Err("external"));
+  clock.advance(Duration::from_secs(10));
+  assert_eq!(0, pending.calls());
+
+  thunk.run(collect(&results));
+  assert_eq!(1, pending.calls());
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn jitter_from_a_fixed_source() {
+  let retry = retry().jitter(0.5).random(|retry| match retry {
+    1 => 0.0,
+    2 => 1.0,
+    _ => f64::NAN
+  });
+
+  assert_eq!(Duration::from_secs(1), retry.delay(1));
+  assert_eq!(Duration::from_secs(1), retry.delay(2));
+  assert_eq!(Duration::from_secs(4), retry.delay(3));
+}
+
+#[test]
+fn invalid_backoff_never_panics() {
+  let negative = retry().multiplier(-2.0).jitter(f64::NAN);
+  assert_eq!(Duration::from_secs(1), negative.delay(1));
+  assert_eq!(Duration::ZERO, negative.delay(2));
+
+  let nan = retry().multiplier(f64::NAN).jitter(-1.0);
+  assert_eq!(Duration::ZERO, nan.delay(3));
+
+  let infinite = Retry::new(3)
+    .backoff(Duration::ZERO, Duration::MAX)
+    .multiplier(f64::INFINITY);
+  assert_eq!(Duration::ZERO, infinite.delay(2));
+
+  let longest = Retry::new(3)
+    .backoff(Duration::from_secs(1), Duration::MAX)
+    .multiplier(f64::INFINITY);
+  assert_eq!(Duration::MAX, longest.delay(2));
+}
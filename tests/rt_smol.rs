@@ -0,0 +1,46 @@
+#![cfg(feature = "rt-smol")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use thunky::rt::{Runtime, Smol};
+use thunky::Thunky;
+
+#[test]
+fn sleep_waits_for_duration() {
+  smol::block_on(async {
+    let start = Instant::now();
+    Smol.sleep(Duration::from_millis(50)).await;
+    assert!(start.elapsed() >= Duration::from_millis(50));
+  });
+}
+
+#[test]
+fn spawn_runs_future() {
+  let (tx, rx) = futures::channel::oneshot::channel();
+  Smol.spawn(Box::pin(async move {
+    tx.send(1).unwrap();
+  }));
+  assert_eq!(1, smol::block_on(rx).unwrap());
+}
+
+#[test]
+fn from_async_on_resolves_waiters_once() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = Thunky::from_async_on(Smol, move || {
+    calls_clone.fetch_add(1, Ordering::SeqCst);
+    async {
+      Smol.sleep(Duration::from_millis(50)).await;
+      Ok::<u32, &str>(1)
+    }
+  });
+
+  let (a, b, c) = smol::block_on(async {
+    futures::join!(thunk.get(), thunk.get(), thunk.get())
+  });
+  assert_eq!((Ok(1), Ok(1), Ok(1)), (a, b, c));
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
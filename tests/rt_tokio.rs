@@ -0,0 +1,97 @@
+#![cfg(feature = "rt-tokio")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use thunky::rt::{Runtime, Tokio};
+use thunky::{Resolver, Retry, RuntimeTimer, Thunky, ThunkyBuilder};
+
+#[tokio::test]
+async fn sleep_waits_for_duration() {
+  let start = Instant::now();
+  Tokio::current().sleep(Duration::from_millis(50)).await;
+  assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn spawn_runs_future() {
+  let (tx, rx) = futures::channel::oneshot::channel();
+  Tokio::current().spawn(Box::pin(async move {
+    tx.send(1).unwrap();
+  }));
+  assert_eq!(1, rx.await.unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn from_async_on_resolves_waiters_once() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = Thunky::from_async_on(Tokio::current(), move || {
+    calls_clone.fetch_add(1, Ordering::SeqCst);
+    async {
+      Tokio::current().sleep(Duration::from_millis(50)).await;
+      Ok::<u32, &str>(1)
+    }
+  });
+
+  let (a, b, c) = tokio::join!(thunk.get(), thunk.get(), thunk.get());
+  assert_eq!((Ok(1), Ok(1), Ok(1)), (a, b, c));
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn from_handle_spawns_outside_runtime() {
+  let runtime = tokio::runtime::Runtime::new().unwrap();
+  let tokio = Tokio::from(runtime.handle().clone());
+  let thunk = Thunky::from_async_on(tokio.clone(), || {
+    async { Ok::<u32, &str>(1) }
+  });
+  assert_eq!(Ok(1), futures::executor::block_on(thunk.get()));
+
+  let start = Instant::now();
+  futures::executor::block_on(tokio.sleep(Duration::from_millis(20)));
+  assert!(start.elapsed() >= Duration::from_millis(20));
+
+  // The waiter times out on the runtime, from a thread outside of it.
+  let resolvers = Arc::new(Mutex::new(Vec::new()));
+  let resolvers_clone = Arc::clone(&resolvers);
+  let thunk = ThunkyBuilder::new()
+    .timer(Arc::new(RuntimeTimer(tokio)))
+    .build(move |resolver: Resolver<u32, &'static str>| {
+      resolvers_clone.lock().unwrap().push(resolver);
+    });
+  let (tx, rx) = mpsc::channel();
+  thunk.run_with_timeout(Duration::from_millis(20), move |arg| {
+    tx.send(*arg).unwrap()
+  });
+  let result = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+  assert_eq!(Err("thunky timed out"), result);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn runtime_timer_retries() {
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = ThunkyBuilder::new()
+    .retry(Retry::new(3).backoff(
+      Duration::from_millis(10),
+      Duration::from_millis(10)
+    ))
+    .timer(Arc::new(RuntimeTimer(Tokio::current())))
+    .build_async_on(Tokio::current(), move || {
+      let calls = calls_clone.fetch_add(1, Ordering::SeqCst);
+      async move {
+        if calls < 2 {
+          Err("down")
+        } else {
+          Ok::<usize, &str>(calls)
+        }
+      }
+    });
+
+  assert_eq!(Ok(2), thunk.get().await);
+  assert_eq!(3, calls.load(Ordering::SeqCst));
+}
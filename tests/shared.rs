@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use common::Pending;
+use futures::executor::block_on;
+use futures::future::join;
+use thunky::{Error, ManualClock, Shared, Thunky, ThunkyBuilder};
+
+/// A value which can't be cloned out of the thunky.
+#[derive(Debug, PartialEq)]
+struct Blob(Vec<u8>);
+
+#[test]
+fn try_get_waits_for_nothing() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+  assert!(thunk.try_get().is_none());
+  assert_eq!(0, pending.calls());
+
+  thunk.run(|_: &Result<u32, &'static str>| {});
+  assert!(thunk.try_get().is_none());
+  assert_eq!(None, thunk.get_cloned());
+
+  pending.resolve(Ok(1));
+  assert_eq!(Ok(&1), thunk.try_get().unwrap().as_result());
+  assert_eq!(Some(Ok(1)), thunk.get_cloned());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn try_get_skips_failed_and_expired_results() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(|_: &Result<u32, &'static str>| {});
+  pending.resolve(Err("down"));
+  assert!(thunk.try_get().is_none());
+
+  thunk.run(|_: &Result<u32, &'static str>| {});
+  pending.resolve(Ok(2));
+  assert_eq!(Some(Ok(2)), thunk.get_cloned());
+
+  clock.advance(Duration::from_secs(10));
+  assert!(thunk.try_get().is_none());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn results_are_shared_with_the_cache() {
+  let pending = Pending::<Blob>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  let (tx, rx) = mpsc::channel();
+  thunk.run_shared(move |result| tx.send(result).unwrap());
+  pending.resolve(Ok(Blob(vec![7; 64])));
+
+  let queued = rx.recv().unwrap();
+  let cached = thunk.try_get().unwrap();
+  let fast = block_on(thunk.get_shared());
+  assert!(Shared::ptr_eq(&queued, &cached));
+  assert!(Shared::ptr_eq(&cached, &fast));
+  assert_eq!(Ok(&Blob(vec![7; 64])), fast.as_result());
+}
+
+#[test]
+fn get_shared_waits_for_the_run() {
+  let pending = Pending::<Blob>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  let resolve = async { pending.resolve(Ok(Blob(vec![1]))) };
+  let (result, ()) = block_on(join(thunk.get_shared(), resolve));
+  assert_eq!(Ok(&Blob(vec![1])), result.as_result());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn shared_callbacks_time_out_like_others() {
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .init_timeout(Duration::from_millis(10))
+    .build(pending.run_fn());
+
+  let (tx, rx) = mpsc::channel();
+  thunk.run_shared(move |result| tx.send(result).unwrap());
+  let result = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+  let timeout: &'static str = Error::Timeout.into();
+  assert_eq!(Err(&timeout), result.as_result());
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use thunky::{ManualClock, Thunky, ThunkyBuilder};
+
+/// A thunky with `Ok(1)` resolved at the start of `clock`.
+fn resolved(
+  builder: ThunkyBuilder,
+  pending: &Arc<Pending<u32>>
+) -> Arc<Thunky<u32, &'static str>> {
+  let thunk = builder.build(pending.run_fn());
+  thunk.run(Box::new(|_arg: &Result<u32, &'static str>| {}));
+  pending.resolve(Ok(1));
+  thunk
+}
+
+fn swr(clock: &Arc<ManualClock>) -> ThunkyBuilder {
+  ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .clock(clock.clone())
+}
+
+#[test]
+fn stale_value_is_served_during_refresh() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock), &pending);
+
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+
+  pending.resolve(Ok(2));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn refreshed_value_gets_a_new_ttl() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock), &pending);
+
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  pending.resolve(Ok(2));
+
+  clock.advance(Duration::from_secs(9));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn failed_refresh_keeps_stale_value() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock), &pending);
+
+  clock.advance(Duration::from_secs(10));
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(3, pending.calls());
+
+  pending.resolve(Ok(2));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn max_stale_bounds_serving() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock).max_stale(Duration::from_secs(5)), &pending);
+
+  clock.advance(Duration::from_secs(14));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(1));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+
+  pending.resolve(Ok(2));
+  assert_eq!(vec![Ok(1), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn longest_max_stale_serves_forever() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock).max_stale(Duration::MAX), &pending);
+
+  clock.advance(Duration::from_secs(60 * 60 * 24 * 365));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+}
+
+#[test]
+fn failed_refresh_past_max_stale_is_delivered() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = resolved(swr(&clock).max_stale(Duration::from_secs(5)), &pending);
+
+  clock.advance(Duration::from_secs(15));
+  thunk.run(collect(&results));
+  pending.resolve(Err("down"));
+  assert_eq!(vec![Err("down")], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(3, pending.calls());
+  assert_eq!(1, results.lock().unwrap().len());
+}
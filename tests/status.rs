@@ -0,0 +1,119 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use thunky::{Clock, ManualClock, Retry, Status, Thunky, ThunkyBuilder};
+
+fn noop(_: &Result<u32, &'static str>) {}
+
+#[test]
+fn status_follows_the_run() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+  assert_eq!(Status::Idle, thunk.status());
+  assert_eq!(0, thunk.attempts());
+  assert_eq!(0, thunk.waiter_count());
+
+  thunk.run(noop);
+  thunk.run(noop);
+  assert_eq!(Status::Initializing, thunk.status());
+  assert_eq!(2, thunk.waiter_count());
+  assert_eq!(1, thunk.attempts());
+
+  clock.advance(Duration::from_secs(1));
+  pending.resolve(Ok(1));
+  assert_eq!(Status::Resolved, thunk.status());
+  assert_eq!(0, thunk.waiter_count());
+  assert_eq!(Some(clock.now()), thunk.resolved_at());
+  assert_eq!(None, thunk.failed_at());
+
+  clock.advance(Duration::from_secs(10));
+  assert_eq!(Status::Expired, thunk.status());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn failed_runs_are_reported() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let second = Duration::from_secs(1);
+  let thunk = ThunkyBuilder::new()
+    .retry(Retry::new(2).backoff(second, second))
+    .clock(clock.clone())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Err("down 1"));
+  assert_eq!(Status::BackingOff, thunk.status());
+  assert_eq!(1, thunk.waiter_count());
+  assert_eq!(Some(clock.now()), thunk.failed_at());
+
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(Status::Initializing, thunk.status());
+  assert_eq!(2, thunk.attempts());
+
+  pending.resolve(Err("down 2"));
+  assert_eq!(Status::Failed, thunk.status());
+  assert_eq!(2, thunk.attempts());
+  assert_eq!(Some(clock.now()), thunk.failed_at());
+  assert_eq!(None, thunk.resolved_at());
+  assert_eq!(vec![Err("down 2")], *results.lock().unwrap());
+
+  thunk.run(collect(&results));
+  assert_eq!(Status::Initializing, thunk.status());
+  assert_eq!(1, thunk.attempts());
+}
+
+#[test]
+fn reset_forgets_the_failure() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+
+  thunk.run(noop);
+  pending.resolve(Err("down"));
+  assert_eq!(Status::Failed, thunk.status());
+
+  thunk.reset();
+  assert_eq!(Status::Idle, thunk.status());
+  assert!(thunk.failed_at().is_some());
+}
+
+#[test]
+fn stale_value_is_refreshing() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(noop);
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+  thunk.run(noop);
+  assert_eq!(Status::Refreshing, thunk.status());
+  assert_eq!(0, thunk.waiter_count());
+
+  pending.resolve(Ok(2));
+  assert_eq!(Status::Resolved, thunk.status());
+}
+
+#[test]
+fn debug_shows_the_status() {
+  let pending = Pending::<u32>::new();
+  let thunk = Thunky::new(pending.run_fn());
+  thunk.run(noop);
+
+  let debug = format!("{:?}", thunk);
+  assert!(debug.starts_with("Thunky { status: Initializing, waiters: 1,"));
+  assert!(debug.contains("attempts: 1"));
+}
@@ -0,0 +1,270 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+use common::{collect, Pending, Results};
+use futures::executor::block_on;
+use futures::future::join;
+use thunky::{
+  ManualClock, Resolver, Retry, Status, ThreadTimer, ThunkyBuilder, Timer
+};
+
+#[test]
+fn waiter_times_out_and_run_goes_on() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let timed = Results::default();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run_with_timeout(Duration::from_secs(1), collect(&timed));
+  thunk.run(collect(&results));
+
+  clock.advance(Duration::from_millis(999));
+  assert!(timed.lock().unwrap().is_empty());
+  clock.advance(Duration::from_millis(1));
+  assert_eq!(vec![Err("thunky timed out")], *timed.lock().unwrap());
+  assert!(results.lock().unwrap().is_empty());
+
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Err("thunky timed out")], *timed.lock().unwrap());
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, pending.calls());
+}
+
+#[test]
+fn longest_timeout_never_fires() {
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .init_timeout(Duration::MAX)
+    .build(pending.run_fn());
+
+  thunk.run_with_timeout(Duration::MAX, collect(&results));
+  assert_eq!(Status::Initializing, thunk.status());
+  assert_eq!(1, pending.calls());
+
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+
+  let clock = Arc::new(ManualClock::new());
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .build(pending.run_fn());
+  thunk.run_with_timeout(Duration::MAX, collect(&results));
+  clock.advance(Duration::from_secs(60 * 60 * 24 * 365));
+  pending.resolve(Ok(2));
+  assert_eq!(vec![Ok(1), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn timeout_after_resolve_is_ignored() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run_with_timeout(Duration::from_secs(1), collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+
+  thunk.run_with_timeout(Duration::from_secs(1), collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn get_with_timeout() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  let timed = thunk.get_with_timeout(Duration::from_secs(1));
+  let waiting = thunk.get();
+  let both = join(timed, async {
+    clock.advance(Duration::from_secs(1));
+    pending.resolve(Ok(1));
+  });
+  let (timed, ()) = block_on(both);
+
+  assert_eq!(Err("thunky timed out"), timed);
+  assert_eq!(Ok(1), block_on(waiting));
+}
+
+#[test]
+fn init_timeout_fails_all_waiters_and_rearms() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .init_timeout(Duration::from_secs(5))
+    .build(pending.run_fn());
+  let generation = thunk.generation();
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(5));
+  assert_eq!(
+    vec![Err("thunky timed out"), Err("thunky timed out")],
+    *results.lock().unwrap()
+  );
+  assert_ne!(generation, thunk.generation());
+
+  // The late resolver of the timed out run is ignored.
+  pending.resolve(Ok(1));
+  assert_eq!(2, results.lock().unwrap().len());
+
+  thunk.run(collect(&results));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(2));
+  assert_eq!(Ok(2), results.lock().unwrap()[2]);
+}
+
+#[test]
+fn init_timeout_is_per_run() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .init_timeout(Duration::from_secs(5))
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(5));
+  pending.take();
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(4));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(
+    vec![Err("thunky timed out"), Ok(1)],
+    *results.lock().unwrap()
+  );
+}
+
+#[test]
+fn init_timeout_is_retried() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let retry = Retry::new(2)
+    .backoff(Duration::from_secs(1), Duration::from_secs(1));
+  let thunk = ThunkyBuilder::new()
+    .timer(clock.clone())
+    .init_timeout(Duration::from_secs(5))
+    .retry(retry)
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(5));
+  assert!(results.lock().unwrap().is_empty());
+  pending.take();
+
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(2, pending.calls());
+  pending.resolve(Ok(1));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+}
+
+#[test]
+fn refresh_times_out() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .stale_while_revalidate()
+    .max_stale(Duration::from_secs(20))
+    .init_timeout(Duration::from_secs(1))
+    .clock(clock.clone())
+    .timer(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+
+  // The refresh is never resolved.
+  thunk.run(collect(&results));
+  assert_eq!(Status::Refreshing, thunk.status());
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(Status::Expired, thunk.status());
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(20));
+  thunk.run(collect(&results));
+  assert_eq!(Status::Initializing, thunk.status());
+  assert_eq!(3, pending.calls());
+
+  clock.advance(Duration::from_secs(1));
+  assert_eq!(0, thunk.waiter_count());
+  assert_eq!(Status::Failed, thunk.status());
+  assert_eq!(
+    vec![Ok(1), Ok(1), Err("thunky timed out")],
+    *results.lock().unwrap()
+  );
+}
+
+#[test]
+fn thread_timer_calls_in_deadline_order() {
+  let (tx, rx) = mpsc::channel();
+  for (name, millis) in [("c", 60), ("a", 20), ("b", 40)] {
+    let tx = tx.clone();
+    ThreadTimer.schedule(
+      Duration::from_millis(millis),
+      Box::new(move || tx.send(name).unwrap())
+    );
+  }
+  ThreadTimer.schedule(Duration::from_millis(30), Box::new(|| panic!("oops")));
+
+  let start = Instant::now();
+  let order: Vec<_> = (0..3)
+    .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+    .collect();
+  assert_eq!(vec!["a", "b", "c"], order);
+  assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn thread_timer_is_not_held_by_a_blocking_retry() {
+  let (started, blocking) = mpsc::channel();
+  let calls = AtomicUsize::new(0);
+  let slow = ThunkyBuilder::new()
+    .retry(Retry::new(2).backoff(
+      Duration::from_millis(10),
+      Duration::from_millis(10)
+    ))
+    .build(Box::new(move |resolver: Resolver<u32, &'static str>| {
+      started.send(()).unwrap();
+      if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+        return resolver.resolve(Err("oops"));
+      }
+      std::thread::sleep(Duration::from_secs(2));
+      resolver.resolve(Ok(1));
+    }));
+  let pending = Pending::<u32>::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new().build(pending.run_fn());
+
+  slow.run(Box::new(|_: &Result<u32, &'static str>| {}));
+  blocking.recv().unwrap();
+  blocking.recv().unwrap();
+  let start = Instant::now();
+  thunk.run_with_timeout(Duration::from_millis(100), collect(&results));
+  while results.lock().unwrap().is_empty() {
+    assert!(start.elapsed() < Duration::from_secs(1));
+    std::thread::sleep(Duration::from_millis(10));
+  }
+  assert_eq!(vec![Err("thunky timed out")], *results.lock().unwrap());
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{collect, Pending, Results};
+use futures::executor::block_on;
+use thunky::{ManualClock, Resolver, Status, Thunky, ThunkyBuilder};
+
+fn counting(
+  builder: ThunkyBuilder,
+  calls: &Arc<AtomicUsize>
+) -> Arc<Thunky<usize, &'static str>> {
+  let calls = Arc::clone(calls);
+  builder.build(Box::new(move |resolver: Resolver<usize, &'static str>| {
+    resolver.resolve(Ok(calls.fetch_add(1, Ordering::SeqCst) + 1));
+  }))
+}
+
+#[test]
+fn value_is_cached_until_ttl() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let thunk = counting(
+    ThunkyBuilder::new()
+      .ttl(Duration::from_secs(10))
+      .clock(clock.clone()),
+    &calls
+  );
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(9));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+
+  clock.advance(Duration::from_secs(1));
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1), Ok(2), Ok(2)], *results.lock().unwrap());
+  assert_eq!(2, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn ttl_restarts_from_each_resolve() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let thunk = counting(
+    ThunkyBuilder::new()
+      .ttl(Duration::from_secs(10))
+      .clock(clock.clone()),
+    &calls
+  );
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(15));
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(9));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(2), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn no_ttl_never_expires() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let thunk = counting(ThunkyBuilder::new().clock(clock.clone()), &calls);
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(60 * 60 * 24 * 365));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn longest_ttl_never_expires() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let thunk = counting(
+    ThunkyBuilder::new().ttl(Duration::MAX).clock(clock.clone()),
+    &calls
+  );
+
+  thunk.run(collect(&results));
+  clock.advance(Duration::from_secs(60 * 60 * 24 * 365));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(1)], *results.lock().unwrap());
+  assert_eq!(Status::Resolved, thunk.status());
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn expired_value_is_deferred_to_waiters() {
+  let clock = Arc::new(ManualClock::new());
+  let pending = Pending::new();
+  let results = Results::default();
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build(pending.run_fn());
+
+  thunk.run(collect(&results));
+  pending.resolve(Ok(1));
+  clock.advance(Duration::from_secs(10));
+
+  thunk.run(collect(&results));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1)], *results.lock().unwrap());
+  assert_eq!(2, pending.calls());
+
+  pending.resolve(Ok(2));
+  assert_eq!(vec![Ok(1), Ok(2), Ok(2)], *results.lock().unwrap());
+}
+
+#[test]
+fn cache_after_expiry_replaces_value() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+  let results = Results::default();
+  let thunk = counting(
+    ThunkyBuilder::new()
+      .ttl(Duration::from_secs(10))
+      .clock(clock.clone()),
+    &calls
+  );
+
+  thunk.run(collect(&results));
+  thunk.cache(Ok(5));
+  clock.advance(Duration::from_secs(10));
+  thunk.cache(Ok(5));
+  thunk.run(collect(&results));
+  assert_eq!(vec![Ok(1), Ok(5)], *results.lock().unwrap());
+  assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[test]
+fn async_init_with_ttl() {
+  let clock = Arc::new(ManualClock::new());
+  let calls = Arc::new(AtomicUsize::new(0));
+
+  let calls_clone = Arc::clone(&calls);
+  let thunk = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build_async(move || {
+      let value = calls_clone.fetch_add(1, Ordering::SeqCst) + 1;
+      async move { Ok::<usize, &str>(value) }
+    });
+
+  assert_eq!(Ok(1), block_on(thunk.get()));
+  assert_eq!(Ok(1), block_on(thunk.get()));
+  clock.advance(Duration::from_secs(10));
+  assert_eq!(Ok(2), block_on(thunk.get()));
+}
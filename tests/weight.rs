@@ -0,0 +1,207 @@
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use thunky::{ManualClock, MapStats, Resolver, ThunkyBuilder, ThunkyMap};
+
+type Map = ThunkyMap<usize, Vec<u8>, &'static str>;
+type Resolvers = Arc<Mutex<Vec<Resolver<Vec<u8>, &'static str>>>>;
+
+/// A map whose values are `key` bytes long, and weigh their length.
+fn sized(builder: ThunkyBuilder) -> Map {
+  builder.build_weighted_map(
+    |value: &Vec<u8>| value.len() as u64,
+    Box::new(|key: &usize, resolver: Resolver<Vec<u8>, &'static str>| {
+      resolver.resolve(Ok(vec![0; *key]))
+    })
+  )
+}
+
+fn touch(map: &Map, key: usize) {
+  map.run(key, Box::new(|_arg: &Result<Vec<u8>, &'static str>| {}));
+}
+
+#[test]
+fn stats_count_hits_and_misses() {
+  let map = sized(ThunkyBuilder::new());
+
+  for key in &[1, 2, 1, 1, 3] {
+    touch(&map, *key);
+  }
+
+  assert_eq!(
+    MapStats {
+      len: 3,
+      weight: 6,
+      hits: 2,
+      misses: 3,
+      evictions: 0,
+      evicted_weight: 0
+    },
+    map.stats()
+  );
+}
+
+#[test]
+fn evicts_least_recently_used_over_budget() {
+  let map = sized(ThunkyBuilder::new().max_weight(100));
+
+  touch(&map, 40);
+  touch(&map, 30);
+  touch(&map, 20);
+  touch(&map, 40);
+  touch(&map, 50);
+  assert!(map.contains_key(&40));
+  assert!(!map.contains_key(&30));
+  assert!(!map.contains_key(&20));
+
+  let stats = map.stats();
+  assert_eq!(90, stats.weight);
+  assert_eq!(2, stats.evictions);
+  assert_eq!(50, stats.evicted_weight);
+}
+
+#[test]
+fn key_in_use_is_kept_over_budget() {
+  let map = sized(ThunkyBuilder::new().max_weight(10));
+
+  touch(&map, 5);
+  touch(&map, 100);
+  touch(&map, 100);
+
+  assert!(map.contains_key(&100));
+  assert!(!map.contains_key(&5));
+  assert_eq!(100, map.stats().weight);
+}
+
+#[test]
+fn running_keys_are_not_evicted() {
+  let resolvers = Resolvers::default();
+
+  let resolvers_clone = Arc::clone(&resolvers);
+  let map = ThunkyBuilder::new().max_weight(10).build_weighted_map(
+    |value: &Vec<u8>| value.len() as u64,
+    Box::new(move |key: &usize, resolver: Resolver<Vec<u8>, &'static str>| {
+      if *key == 0 {
+        resolvers_clone.lock().unwrap().push(resolver);
+      } else {
+        resolver.resolve(Ok(vec![0; *key]));
+      }
+    })
+  );
+
+  touch(&map, 0);
+  touch(&map, 20);
+  touch(&map, 30);
+  assert!(map.contains_key(&0));
+  assert!(!map.contains_key(&20));
+  assert!(map.contains_key(&30));
+
+  resolvers.lock().unwrap().pop().unwrap().resolve(Ok(vec![0; 5]));
+  assert!(map.contains_key(&0));
+  assert!(!map.contains_key(&30));
+  assert_eq!(5, map.stats().weight);
+}
+
+#[test]
+fn failed_and_expired_values_weigh_nothing() {
+  let clock = Arc::new(ManualClock::new());
+  let map: Map = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build_weighted_map(
+      |value: &Vec<u8>| value.len() as u64,
+      Box::new(|key: &usize, resolver: Resolver<Vec<u8>, &'static str>| {
+        if *key == 0 {
+          resolver.resolve(Err("down"));
+        } else {
+          resolver.resolve(Ok(vec![0; *key]));
+        }
+      })
+    );
+
+  touch(&map, 0);
+  touch(&map, 10);
+  touch(&map, 20);
+  assert_eq!(30, map.stats().weight);
+
+  clock.advance(Duration::from_secs(10));
+  touch(&map, 30);
+  assert_eq!(30, map.stats().weight);
+}
+
+#[test]
+fn remove_subtracts_weight() {
+  let map = sized(ThunkyBuilder::new());
+
+  touch(&map, 10);
+  touch(&map, 20);
+  touch(&map, 30);
+  assert_eq!(60, map.stats().weight);
+
+  map.remove(&20);
+  assert_eq!(40, map.stats().weight);
+  assert_eq!(0, map.stats().evictions);
+}
+
+#[test]
+fn values_are_weighed_when_resolved() {
+  let map = sized(ThunkyBuilder::new().max_weight(1024));
+
+  touch(&map, 1 << 20);
+  assert_eq!(1 << 20, map.stats().weight);
+
+  touch(&map, 1 << 21);
+  assert!(!map.contains_key(&(1 << 20)));
+  let stats = map.stats();
+  assert_eq!(1 << 21, stats.weight);
+  assert_eq!(1, stats.evictions);
+  assert_eq!(1 << 20, stats.evicted_weight);
+}
+
+#[test]
+fn capacity_and_budget_combine() {
+  let map = sized(ThunkyBuilder::new().capacity(2).max_weight(100));
+
+  touch(&map, 1);
+  touch(&map, 2);
+  touch(&map, 3);
+  assert_eq!(2, map.len());
+  assert_eq!(1, map.stats().evictions);
+}
+
+#[test]
+fn misses_only_weigh_new_and_expired_values() {
+  let clock = Arc::new(ManualClock::new());
+  let weighed = Arc::new(AtomicUsize::new(0));
+
+  let weighed_clone = Arc::clone(&weighed);
+  let map: Map = ThunkyBuilder::new()
+    .ttl(Duration::from_secs(10))
+    .clock(clock.clone())
+    .build_weighted_map(
+      move |value: &Vec<u8>| {
+        weighed_clone.fetch_add(1, Ordering::SeqCst);
+        value.len() as u64
+      },
+      Box::new(|key: &usize, resolver: Resolver<Vec<u8>, &'static str>| {
+        resolver.resolve(Ok(vec![0; *key]))
+      })
+    );
+
+  for key in 1..=100 {
+    touch(&map, key);
+  }
+  assert_eq!(100, weighed.load(Ordering::SeqCst));
+
+  clock.advance(Duration::from_secs(5));
+  touch(&map, 1);
+  touch(&map, 101);
+  assert_eq!(101, weighed.load(Ordering::SeqCst));
+
+  clock.advance(Duration::from_secs(5));
+  touch(&map, 102);
+  assert_eq!(102, weighed.load(Ordering::SeqCst));
+  assert_eq!(203, map.stats().weight);
+}